@@ -0,0 +1,135 @@
+//! Downloaded homework attachments, kept under `attachments/<subdomain>/<entry-id>/`.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use smh;
+use interface;
+use output;
+use sidecar;
+use digest;
+
+/// What happened to an attachment on `Store::save`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Saved {
+    AlreadyOnDisk, // The file was there already, nothing was fetched
+    Linked       , // The same content was on disk elsewhere and was linked in
+    Downloaded   ,
+}
+
+// Which content each attachment had and where a copy of each content lives.
+// Files are never deleted, so attachments outlive entries that go upstream.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    by_attachment : HashMap<String, String>, // `subdomain/attachment-id` to content hash
+    by_hash       : HashMap<String, String>, // Content hash to a path, relative to the store, holding that content
+}
+
+/// The attachment store in a data directory.
+pub struct Store {
+    root       : PathBuf,
+    index_path : PathBuf,
+    index      : Index  ,
+}
+
+// Stored in the index, so it has to mean the same thing in every build. Hashes
+// from older builds just stop matching, and that content is fetched once more.
+fn content_hash(bytes: &[u8]) -> String {
+    format!("sha256-{}", digest::sha256(bytes))
+}
+
+// Attachment names come from SMHW, so don't let them climb out of the entry's directory
+fn safe_file_name(attachment: &smh::Attachment) -> String {
+    let name: String = attachment.file_name
+                                 .chars()
+                                 .map(|c| if c == '/' || c == '\\' || c == '\0' { '_' } else { c })
+                                 .collect();
+    match name.trim_matches('.').is_empty() {
+        true  => format!("attachment-{}", attachment.id),
+        false => name                                   ,
+    }
+}
+
+impl Store {
+    /// Opens (creating if needed) the store under `data_dir/attachments`.
+    pub fn open(data_dir: &PathBuf) -> Result<Store, output::Message> {
+        let mut root = (*data_dir).clone();
+        root.push("attachments");
+        fs::create_dir_all(&root)?;
+
+        let mut index_path = root.clone();
+        index_path.push("index");
+        index_path.set_extension("json");
+        OpenOptions::new().create(true).read(true).write(true).open(&index_path)?;
+
+        let index = sidecar::load(&index_path, "Attachment Index")?;
+        Ok(Store {root: root, index_path: index_path, index: index})
+    }
+
+    /// Where an attachment of an entry is, or would be, saved.
+    pub fn path_for(&self, subdomain: &str, entry_id: i32, attachment: &smh::Attachment) -> PathBuf {
+        let mut path = self.root.clone();
+        path.push(subdomain);
+        path.push(entry_id.to_string());
+        path.push(safe_file_name(attachment));
+        path
+    }
+
+    pub fn is_saved(&self, subdomain: &str, entry_id: i32, attachment: &smh::Attachment) -> bool {
+        self.path_for(subdomain, entry_id, attachment).exists()
+    }
+
+    // Older indexes held absolute paths, `join` keeps those as they were
+    fn indexed(&self, hash: &str) -> Option<PathBuf> {
+        self.index.by_hash.get(hash)
+                          .map(|rel| self.root.join(rel))
+                          .filter(|existing| existing.exists())
+    }
+
+    // Hard link to a copy we already have, falling back to copying where links aren't supported
+    fn link(from: &PathBuf, to: &PathBuf) -> Result<(), output::Message> {
+        if fs::hard_link(from, to).is_err() {
+            fs::copy(from, to)?;
+        }
+        Ok(())
+    }
+
+    /// Saves one attachment, downloading it only if its content isn't on disk yet.
+    pub fn save(&mut self, interface: &interface::Interface, subdomain: &str, entry_id: i32, attachment: &smh::Attachment) -> Result<Saved, output::Message> {
+        let path = self.path_for(subdomain, entry_id, attachment);
+        if path.exists() {
+            return Ok(Saved::AlreadyOnDisk);
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let att_key = format!("{}/{}", subdomain, attachment.id);
+        let known   = self.index.by_attachment.get(&att_key)
+                                              .and_then(|hash| self.indexed(hash));
+        if let Some(existing) = known {
+            debug!("Attachment {} already at {}, linking", attachment.id, existing.display());
+            Store::link(&existing, &path)?;
+            return Ok(Saved::Linked);
+        }
+
+        let bytes = interface.download(&attachment.file_url)?;
+        let hash  = content_hash(&bytes);
+
+        let saved = match self.indexed(&hash) {
+            Some(existing) => {Store::link(&existing, &path)?;
+                               Saved::Linked},
+            None           => {File::create(&path)?.write_all(&bytes)?;
+                               let rel = path.strip_prefix(&self.root).unwrap_or(&path).to_string_lossy().into_owned();
+                               self.index.by_hash.insert(hash.clone(), rel);
+                               Saved::Downloaded},
+        };
+
+        self.index.by_attachment.insert(att_key, hash);
+        sidecar::save(&self.index, &self.index_path, "Attachment Index")?;
+        Ok(saved)
+    }
+}
@@ -0,0 +1,86 @@
+//! Login tokens for student and parent accounts, kept in `auth.json`.
+
+extern crate time;
+
+use std::path::PathBuf;
+use std::fs;
+use std::fs::OpenOptions;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+use output;
+use sidecar;
+
+/// An OAuth token from SMHW, with what's needed to refresh it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Token {
+    pub access_token  : String,
+    pub refresh_token : String,
+    pub expires_at    : i64   , // Unix time
+    pub subdomain     : String,
+    pub school_id     : i32   ,
+    pub client_id     : String,
+    pub client_secret : String,
+}
+
+impl Token {
+    // Refresh a minute early rather than race the server
+    pub fn is_expired(&self) -> bool {
+        time::get_time().sec + 60 >= self.expires_at
+    }
+}
+
+/// A stored token and the file it lives in.
+#[derive(Debug)]
+pub struct Session {
+    path      : PathBuf,
+    pub token : Token  ,
+}
+
+pub fn token_path(richter_dir: &PathBuf) -> PathBuf {
+    let mut path = (*richter_dir).clone();
+    path.push("auth");
+    path.set_extension("json");
+    path
+}
+
+impl Session {
+    /// The session stored in a richter directory, if anyone has logged in.
+    pub fn load(richter_dir: &PathBuf) -> Result<Option<Session>, output::Message> {
+        let path = token_path(richter_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let token: Option<Token> = sidecar::load(&path, "Login Token")?;
+        Ok(token.map(|t| Session {path: path, token: t}))
+    }
+
+    /// Stores a new token, readable only by the current user.
+    pub fn create(richter_dir: &PathBuf, token: Token) -> Result<Session, output::Message> {
+        fs::create_dir_all(richter_dir)?;
+        let session = Session {path: token_path(richter_dir), token: token};
+        session.save()?;
+        Ok(session)
+    }
+
+    // The file is created 0600, and tightened in case it was made some other way
+    fn save(&self) -> Result<(), output::Message> {
+        OpenOptions::new().create(true).write(true).mode(0o600).open(&self.path)?;
+        fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600))?;
+        sidecar::save(&Some(self.token.clone()), &self.path, "Login Token")
+    }
+
+    pub fn replace(&mut self, token: Token) -> Result<(), output::Message> {
+        self.token = token;
+        self.save()
+    }
+}
+
+/// Forgets the stored token, if there is one.
+pub fn logout(richter_dir: &PathBuf) -> Result<bool, output::Message> {
+    let path = token_path(richter_dir);
+    if !path.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(&path)?;
+    Ok(true)
+}
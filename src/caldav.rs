@@ -0,0 +1,332 @@
+//! Pushing entries to a CalDAV collection as VTODOs, for `richter sync caldav`.
+//!
+//! The collection comes from the optional `caldav` section of `calendar.yml`:
+//!
+//! ```yaml
+//! caldav:
+//!   url: https://dav.example.com/alice/homework/
+//!   username: alice   # Optional, for HTTP Basic authentication
+//! ```
+//!
+//! `calendar.yml` is often shared or backed up, so the password is not kept
+//! there. It comes from `RICHTER_CALDAV_PASSWORD` if that is set, otherwise
+//! from `caldav-password` in the profile's data directory, which must be
+//! readable only by its owner (`chmod 600`).
+//!
+//! What was last uploaded for each entry, and the ETag the server answered
+//! with, is kept in `caldav.json` beside the other local state. Entries that
+//! haven't changed since are not sent again, and uploads are conditional on
+//! that ETag so edits made on the server are reported rather than overwritten.
+
+extern crate yaml_rust;
+extern crate reqwest;
+extern crate hyper;
+extern crate time;
+
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use self::yaml_rust::{YamlLoader, Yaml};
+use self::hyper::mime::Mime;
+use self::hyper::header::{ContentType, ETag, EntityTag, IfMatch, IfNoneMatch, Authorization, Basic};
+use self::hyper::status::StatusCode;
+
+use calendar;
+use ical;
+use output;
+use sidecar;
+
+/// Where to sync to.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub url      : String        , // The collection, ending in `/`
+    pub username : Option<String>,
+    pub password : Option<String>, // Never from `calendar.yml`, see `password`
+}
+
+/// The environment variable checked for the CalDAV password first.
+pub const PASSWORD_VAR : &'static str = "RICHTER_CALDAV_PASSWORD";
+
+/// Where the CalDAV password is kept when it isn't in the environment.
+pub fn password_path(data_dir: &PathBuf) -> PathBuf {
+    let mut path = data_dir.clone();
+    path.push("caldav-password");
+    path
+}
+
+/// The password from `PASSWORD_VAR`, or else the first line of
+/// `password_path`, if either is there.
+pub fn password(data_dir: &PathBuf) -> Result<Option<String>, output::Message> {
+    if let Ok(password) = env::var(PASSWORD_VAR) {
+        return Ok(Some(password));
+    }
+
+    let path = password_path(data_dir);
+    if !path.exists() {
+        return Ok(None);
+    }
+    if fs::metadata(&path)?.permissions().mode() & 0o077 != 0 {
+        return Err(output::Message::new("CalDAV",
+                                        "Reading Password",
+                                        &format!("{} can be read by other users, run chmod 600 on it", path.display())));
+    }
+    let mut raw = String::new();
+    File::open(&path)?.read_to_string(&mut raw)?;
+    Ok(raw.lines().next().map(|line| line.to_owned()))
+}
+
+fn optional_string(section: &Yaml, key: &str) -> Result<Option<String>, output::Message> {
+    match section[key] {
+        Yaml::String(ref s) => Ok(Some(s.clone())),
+        Yaml::BadValue      => Ok(None)           ,
+        _                   => Err(output::Message::new("Reading YAML File",
+                                                        "Loading CalDAV Settings",
+                                                        &format!("`{}` is not a String", key))),
+    }
+}
+
+impl Settings {
+    /// Reads the `caldav` section, `None` if there isn't one, with the
+    /// password from `data_dir` or the environment.
+    pub fn load(path: &PathBuf, data_dir: &PathBuf) -> Result<Option<Settings>, output::Message> {
+        let mut f = File::open(path)?;
+        let mut raw = String::new();
+        f.read_to_string(&mut raw)?;
+
+        let docs = YamlLoader::load_from_str(&raw)?;
+        let section = match docs.first() {
+            Some(doc) if !doc["caldav"].is_badvalue() => &doc["caldav"],
+            _                                         => return Ok(None),
+        };
+
+        let mut url = match optional_string(section, "url")? {
+            Some(url) => url,
+            None      => return Err(output::Message::new("Reading YAML File",
+                                                         "Loading CalDAV Settings",
+                                                         "`caldav` has no `url` for the collection")),
+        };
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        if !section["password"].is_badvalue() {
+            warn!("Ignoring the CalDAV `password` in {}, set {} or put it in {}",
+                  path.display(), PASSWORD_VAR, password_path(data_dir).display());
+        }
+        Ok(Some(Settings {url     : url,
+                          username: optional_string(section, "username")?,
+                          password: password(data_dir)?,}))
+    }
+}
+
+/// What was last uploaded for one entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pushed {
+    pub title  : String        ,
+    pub due    : String        , // As `ical::date`, empty if there was none
+    pub status : String        , // `NEEDS-ACTION`, `COMPLETED` or `CANCELLED`
+    pub etag   : Option<String>, // Not every server sends one back
+}
+
+/// Everything uploaded from one profile, by UID.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    items : BTreeMap<String, Pushed>,
+}
+
+/// What a sync did.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub created   : usize      ,
+    pub updated   : usize      ,
+    pub cancelled : usize      ,
+    pub unchanged : usize      ,
+    pub conflicts : Vec<String>, // Titles of items changed on the server and left alone
+}
+
+enum Put {
+    Stored(Option<String>), // With the new ETag, if any
+    Conflict              ,
+}
+
+// What the server must hold for an upload to go ahead
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Anything       , // With `force`, or when the server gave no ETag to check against
+    Nothing        , // Never uploaded, so nothing should be there yet
+    Matches(String), // The ETag from the last upload
+}
+
+// Unconditional with `force`, otherwise only over the version last seen
+fn condition(last: Option<&Pushed>, force: bool) -> Condition {
+    match (force, last.map(|l| l.etag.as_ref())) {
+        (true , _               ) => Condition::Anything,
+        (false, None            ) => Condition::Nothing,
+        (false, Some(None)      ) => Condition::Anything,
+        (false, Some(Some(etag))) => Condition::Matches(etag.clone()),
+    }
+}
+
+// Whether the server already has what would be sent, going by the last upload
+fn unchanged(last: Option<&Pushed>, pushed: &Pushed, force: bool) -> bool {
+    !force && last.map_or(false, |l| Pushed {etag: None, ..l.clone()} == Pushed {etag: None, ..pushed.clone()})
+}
+
+struct Client {
+    http     : reqwest::Client,
+    settings : Settings       ,
+}
+
+impl Client {
+    fn put(&self, uid: &str, body: String, condition: Condition) -> Result<Put, output::Message> {
+        let url = format!("{}{}.ics", self.settings.url, uid.split('@').next().unwrap_or(uid));
+        info!("PUT {}", url);
+
+        let mime = Mime::from_str("text/calendar; charset=utf-8")
+                        .map_err(|_| output::Message::new("CalDAV", "Building Request", "Bad Content-Type MIME type"))?;
+        let mut builder = self.http.request(reqwest::Method::Put, &url)
+                                   .header(ContentType(mime))
+                                   .body(body);
+        if let Some(ref username) = self.settings.username {
+            builder = builder.header(Authorization(Basic {username: username.clone(), password: self.settings.password.clone()}));
+        }
+        builder = match condition {
+            Condition::Anything      => builder,
+            Condition::Nothing       => builder.header(IfNoneMatch::Any),
+            Condition::Matches(etag) => match EntityTag::from_str(&etag) {
+                Ok(tag) => builder.header(IfMatch::Items(vec![tag])),
+                Err(_)  => builder,
+            },
+        };
+
+        let response = builder.send()?;
+        debug!("{} responded {}", url, response.status());
+        match *response.status() {
+            StatusCode::PreconditionFailed => Ok(Put::Conflict),
+            s if s.is_success()            => Ok(Put::Stored(response.headers().get::<ETag>().map(|t| t.0.to_string()))),
+            s                              => Err(output::Message::new("CalDAV",
+                                                                       "Uploading VTODO",
+                                                                       &format!("{} responded {}", url, s))),
+        }
+    }
+}
+
+fn vtodo(uid: &str, pushed: &Pushed, details: &[(&str, &str)]) -> String {
+    let mut todo = ical::Component::new("VTODO");
+    todo.raw("UID", uid)
+        .raw("DTSTAMP", &ical::stamp(&time::now_utc()))
+        .text("SUMMARY", &pushed.title)
+        .raw("STATUS", &pushed.status);
+    if !pushed.due.is_empty() {
+        todo.raw("DUE;VALUE=DATE", &pushed.due);
+    }
+    for &(name, value) in details {
+        todo.text(name, value);
+    }
+    ical::calendar(None, &[todo])
+}
+
+// Keeps what was stored, or counts the conflict
+fn record(state: &mut State, summary: &mut Summary, uid: String, mut pushed: Pushed, put: Put) {
+    match put {
+        Put::Conflict     => {warn!("`{}` differs on the CalDAV server, not overwriting it without --force", pushed.title);
+                              summary.conflicts.push(pushed.title);},
+        Put::Stored(etag) => {pushed.etag = etag;
+                              state.items.insert(uid, pushed);},
+    }
+}
+
+/// The UID an entry is always uploaded under.
+pub fn uid(subdomain: &str, id: i32) -> String {
+    format!("smhw-{}-{}@richter", subdomain, id)
+}
+
+/// Uploads the calendar's new and changed entries, and cancels ones no longer
+/// in the cache. With `force`, edits made on the server are overwritten too.
+pub fn sync(cal: &calendar::Calendar, settings: Settings, force: bool) -> Result<Summary, output::Message> {
+    let mut state_path = cal.profile().data_dir();
+    state_path.push("caldav");
+    state_path.set_extension("json");
+    OpenOptions::new().create(true).read(true).write(true).open(&state_path)?;
+    let mut state: State = sidecar::load(&state_path, "CalDAV State")?;
+
+    let client      = Client {http: reqwest::Client::new()?, settings: settings};
+    let mut summary = Summary::default();
+    let mut present = HashSet::new();
+
+    for (enrollment, entry) in cal.entries() {
+        let uid    = uid(&enrollment.subdomain, entry.id);
+        let status = if cal.is_done(enrollment, entry) { "COMPLETED" } else { "NEEDS-ACTION" };
+        let pushed = Pushed {title : entry.title.clone(),
+                             due   : ical::date(&entry.due).unwrap_or(String::new()),
+                             status: status.to_owned(),
+                             etag  : None,};
+        present.insert(uid.clone());
+
+        let last = state.items.get(&uid).cloned();
+        if unchanged(last.as_ref(), &pushed, force) {
+            summary.unchanged += 1;
+            continue;
+        }
+
+        let teacher = cal.teacher(enrollment, entry).map_or(String::new(), |t| t.display_name());
+        let body = vtodo(&uid, &pushed, &[("CATEGORIES" , &entry.subject_name),
+                                          ("DESCRIPTION", &format!("{} {}", entry.class_name, teacher).trim()),
+                                          ("X-SMHW-ID"  , &entry.id.to_string())]);
+        let put = client.put(&uid, body, condition(last.as_ref(), force))?;
+        if let Put::Stored(_) = put {
+            if last.is_some() { summary.updated += 1 } else { summary.created += 1 }
+        }
+        record(&mut state, &mut summary, uid, pushed, put);
+        // Saved as we go, so a failure part way doesn't forget what was uploaded
+        sidecar::save(&state, &state_path, "CalDAV State")?;
+    }
+
+    let removed: Vec<(String, Pushed)> = state.items.iter()
+                                                    .filter(|&(uid, pushed)| !present.contains(uid) && pushed.status != "CANCELLED")
+                                                    .map(|(uid, pushed)| (uid.clone(), pushed.clone()))
+                                                    .collect();
+    for (uid, last) in removed {
+        let pushed = Pushed {status: "CANCELLED".to_owned(), etag: None, ..last.clone()};
+        let put    = client.put(&uid, vtodo(&uid, &pushed, &[]), condition(Some(&last), force))?;
+        if let Put::Stored(_) = put {
+            summary.cancelled += 1;
+        }
+        record(&mut state, &mut summary, uid, pushed, put);
+        sidecar::save(&state, &state_path, "CalDAV State")?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pushed(status: &str, etag: Option<&str>) -> Pushed {
+        Pushed {title: "Fractions".to_owned(), due: "20170220".to_owned(), status: status.to_owned(), etag: etag.map(|e| e.to_owned())}
+    }
+
+    #[test]
+    fn uploads_are_conditional_on_the_last_etag() {
+        assert_eq!(condition(None, false), Condition::Nothing);
+        assert_eq!(condition(Some(&pushed("NEEDS-ACTION", Some("\"v1\""))), false), Condition::Matches("\"v1\"".to_owned()));
+        assert_eq!(condition(Some(&pushed("NEEDS-ACTION", None)), false), Condition::Anything);
+        assert_eq!(condition(Some(&pushed("NEEDS-ACTION", Some("\"v1\""))), true), Condition::Anything);
+        assert_eq!(condition(None, true), Condition::Anything);
+    }
+
+    #[test]
+    fn only_changes_are_sent_again() {
+        let last = pushed("NEEDS-ACTION", Some("\"v1\""));
+        assert!( unchanged(Some(&last), &pushed("NEEDS-ACTION", None), false));
+        assert!(!unchanged(Some(&last), &pushed("COMPLETED"   , None), false));
+        assert!(!unchanged(Some(&last), &Pushed {due: "20170221".to_owned(), ..pushed("NEEDS-ACTION", None)}, false));
+        assert!(!unchanged(Some(&last), &pushed("NEEDS-ACTION", None), true));
+        assert!(!unchanged(None, &pushed("NEEDS-ACTION", None), false));
+    }
+}
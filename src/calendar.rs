@@ -1,4 +1,7 @@
+//! The local cache of SMHW data and the `Calendar` built around it.
+
 extern crate serde_json  ;
+extern crate time        ;
 
 use std::collections::HashMap;
 use std::collections::HashSet;
@@ -9,12 +12,22 @@ use std::path::PathBuf;
 use std::fs;
 use std::fs::{File, OpenOptions};
 use std::error::Error;
+use std::cmp::Ordering;
 
 use smh;
 use interface;
 use enroll;
 use output;
-
+use completion;
+use notes;
+use history;
+use profile;
+use sidecar;
+use query;
+use search;
+use clashes;
+
+/// Everything richter keeps about one school, keyed by the names SMHW uses in entries.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SchoolCache {
     pub school    : smh::School                   ,
@@ -24,10 +37,14 @@ pub struct SchoolCache {
     pub classes   : HashMap<String, smh::Class   >,
 }
 
+/// The on-disk cache of schools and entries, as last pulled from SMHW.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Cache {
-    pub schools : HashMap<i32                , SchoolCache    >,
-    pub entries : HashMap<enroll::Enrollment , Vec<smh::Entry>>,
+    #[serde(default, skip_serializing)]
+    pub schools : HashMap<i32                , SchoolCache    >, // Stored once for all profiles, see `profile`
+    pub entries : Vec<(enroll::Enrollment    , Vec<smh::Entry>)>, // In `calendar.yml` order. JSON keys must be strings, so not a map
+    #[serde(default)]
+    pub homework: HashMap<String             , HashMap<i32, smh::Homework>>, // By subdomain, then entry id
 }
 
 impl Cache {
@@ -36,36 +53,143 @@ impl Cache {
     }
 
     fn add_entries(&mut self, e: enroll::Enrollment, entries: Vec<smh::Entry>) {
-        self.entries.insert(e, entries);
+        self.entries.push((e, entries));
     }
 }
 
+/// A name in an enrollment that SMHW doesn't know at its school, so it will never match anything.
+#[derive(Debug, Clone)]
+pub struct UnknownName {
+    pub enrollment  : enroll::Enrollment,
+    pub field       : &'static str      , // `class`, `year`, `subject`, `pattern` or `school`
+    pub name        : String            ,
+    pub suggestions : Vec<String>       , // Closest known names first
+}
+
+// Levenshtein distance, by chars rather than bytes
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + if ca == *cb { 0 } else { 1 };
+            row.push(*[prev[j + 1] + 1, row[j] + 1, substitute].iter().min().unwrap());
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+// A name differing only in case is almost certainly the one, otherwise
+// anything within a few edits, closest first
+fn suggest<'a, I: Iterator<Item=&'a String>>(name: &str, known: I) -> Vec<String> {
+    let wanted = name.to_lowercase();
+    let known: Vec<&String> = known.collect();
+    let same_case: Vec<String> = known.iter().filter(|k| k.to_lowercase() == wanted).map(|k| (*k).clone()).collect();
+    if !same_case.is_empty() {
+        return same_case;
+    }
+
+    let limit = ::std::cmp::max(2, wanted.chars().count() / 3);
+    let mut close: Vec<(usize, &String)> = known.into_iter()
+                                                .map(|k| (edit_distance(&wanted, &k.to_lowercase()), k))
+                                                .filter(|&(d, _)| d <= limit)
+                                                .collect();
+    close.sort();
+    close.into_iter().take(3).map(|(_, k)| k.clone()).collect()
+}
+
 impl Cache {
+    // Checks each name in a rule against the school's class, year and subject lists
+    fn unknown_in_rule(rule: &enroll::Rule, sc: &SchoolCache, enrollment: &enroll::Enrollment, unknown: &mut Vec<UnknownName>) {
+        let mut report = |field, name: &String, suggestions| unknown.push(UnknownName {enrollment : enrollment.clone(),
+                                                                                       field      : field,
+                                                                                       name       : name.clone(),
+                                                                                       suggestions: suggestions});
+        if let Some(ref class) = rule.class {
+            if !sc.classes.contains_key(class) {
+                report("class", class, suggest(class, sc.classes.keys()));
+            }
+        }
+        if let Some(ref year) = rule.year {
+            if !sc.years.contains_key(year) {
+                report("year", year, suggest(year, sc.years.keys()));
+            }
+        }
+        if let Some(ref subject) = rule.subject {
+            if !sc.subjects.contains_key(subject) {
+                report("subject", subject, suggest(subject, sc.subjects.keys()));
+            }
+        }
+        if let Some(ref pattern) = rule.pattern {
+            if !sc.classes.keys().any(|c| enroll::glob_match(pattern, c)) {
+                report("pattern", pattern, vec![]);
+            }
+        }
+    }
+
+    /// Names in enrollments, exceptions included, that aren't in the cached lists for their school.
+    pub fn unknown_names(&self, enrollments: &Vec<enroll::Enrollment>) -> Vec<UnknownName> {
+        let mut unknown = vec![];
+        for enrollment in enrollments {
+            match self.schools.get(&enrollment.school_id) {
+                Some(sc) => for rule in Some(&enrollment.rule).into_iter().chain(enrollment.except.iter()) {
+                    Cache::unknown_in_rule(rule, sc, enrollment, &mut unknown);
+                },
+                None     => unknown.push(UnknownName {enrollment : enrollment.clone(),
+                                                      field      : "school",
+                                                      name       : enrollment.school_id.to_string(),
+                                                      suggestions: vec![]}),
+            }
+        }
+        unknown
+    }
+
+    // Entries are matched to enrollments by exact name, so a typo would
+    // otherwise just look like a child with no homework
+    fn warn_unknown_names(&self, enrollments: &Vec<enroll::Enrollment>) {
+        for unknown in self.unknown_names(enrollments) {
+            let e = &unknown.enrollment;
+            if unknown.field == "school" {
+                warn!("No school with id {} at {}, enrollment in `{}` will have no entries", e.school_id, e.subdomain, e.label());
+            }
+            else if unknown.field == "pattern" {
+                warn!("No class at {} matches pattern `{}`", e.subdomain, unknown.name);
+            }
+            else if unknown.suggestions.is_empty() {
+                warn!("No {} `{}` at {}, enrollment in `{}` won't match it", unknown.field, unknown.name, e.subdomain, e.label());
+            }
+            else {
+                warn!("No {} `{}` at {}, did you mean {}?", unknown.field, unknown.name, e.subdomain,
+                      unknown.suggestions.iter().map(|c| format!("`{}`", c)).collect::<Vec<String>>().join(" or "));
+            }
+        }
+    }
+
     fn new() -> Cache {
-        Cache {schools: HashMap::new(), entries: HashMap::new()}
+        Cache {schools: HashMap::new(), entries: vec![], homework: HashMap::new()}
     }
 
+    // An entry goes to the first enrollment at its subdomain that takes it
     fn sort_entries(into       : &mut HashMap<enroll::Enrollment, Vec<smh::Entry>>,
+                    subdomain  : &str                                             ,
                     entries    : Vec<smh::Entry>                                  ,
                     enrollments: &Vec<enroll::Enrollment>                         ,)  {
 
-        let mut class_map : HashMap<String, &enroll::Enrollment> = HashMap::new();
-        
-        for enrollment in enrollments {
-            class_map.insert(enrollment.class.to_string(), enrollment);
-        }
+        let at_subdomain: Vec<&enroll::Enrollment> = enrollments.iter().filter(|e| e.subdomain == subdomain).collect();
 
         for entry in entries {
-            if let Some(enrollment) = class_map.get(&entry.class_name) {
-                match into.contains_key(enrollment) {
-                    true  => {into.get_mut(enrollment).unwrap().push(entry)  ; }, 
-                    false => {into.insert((*enrollment).clone(), vec![entry]); },
-                }
+            if let Some(enrollment) = at_subdomain.iter().find(|e| e.matches(&entry)) {
+                into.entry((*enrollment).clone()).or_insert(vec![]).push(entry);
             }
         }
     }
 
-    fn pull(enrollments: &Vec<enroll::Enrollment>) -> Result<Option<Cache>, output::Message> {
+    // `known` is the homework from the last cache, kept rather than fetched again
+    fn pull(interface  : &interface::Interface                          ,
+            enrollments: &Vec<enroll::Enrollment>                       ,
+            known      : HashMap<String, HashMap<i32, smh::Homework>>   ,) -> Result<Option<Cache>, output::Message> {
         let mut school_ids: HashSet<i32>    = HashSet::new();
         let mut subdomains: HashSet<String> = HashSet::new();
 
@@ -74,17 +198,16 @@ impl Cache {
             subdomains.insert(enrollment.subdomain);
         }
 
-        let interface = interface::Interface::new();
-
         let mut pulled_schs    : HashMap<i32               , smh::School    > = HashMap::new();
         let mut pulled_entries : HashMap<enroll::Enrollment, Vec<smh::Entry>> = HashMap::new();
 
         for subdomain in subdomains {
+            debug!("Pulling schools and entries for subdomain {}", subdomain);
             let pulled = interface.get_schools(&subdomain)?;
             for sch in pulled {
                 pulled_schs.insert(sch.id, sch);
             }
-            Cache::sort_entries(&mut pulled_entries, interface.get_entries(&subdomain)?, &enrollments);
+            Cache::sort_entries(&mut pulled_entries, &subdomain, interface.get_entries(&subdomain)?, &enrollments);
         }
         
         let mut cache = Cache::new();
@@ -130,9 +253,37 @@ impl Cache {
             }
         }
 
+        cache.warn_unknown_names(enrollments);
+
+        cache.pull_homework(&interface, known);
+
         Ok(Some(cache))
     }
 
+    // Details are a nicety on top of the entries, one that fails to come
+    // through shouldn't cost us the whole pull. Only entries new since the
+    // last pull are fetched, the rest come from `known`
+    fn pull_homework(&mut self, interface: &interface::Interface, mut known: HashMap<String, HashMap<i32, smh::Homework>>) {
+        let mut homework: HashMap<String, HashMap<i32, smh::Homework>> = HashMap::new();
+        for &(ref enrollment, ref entries) in &self.entries {
+            for entry in entries {
+                let cached = known.get_mut(&enrollment.subdomain).and_then(|by_id| by_id.remove(&entry.id));
+                let fetched = match cached {
+                    Some(hw) => Ok(hw),
+                    None     => {debug!("Fetching details for entry {}", entry.id);
+                                 interface.get_homework(&enrollment.subdomain, entry.id)},
+                };
+                match fetched {
+                    Ok (hw)  => {homework.entry(enrollment.subdomain.clone())
+                                         .or_insert(HashMap::new())
+                                         .insert(entry.id, hw);},
+                    Err(msg) => warn!("No details for entry {}: {}", entry.id, msg),
+                }
+            }
+        }
+        self.homework = homework;
+    }
+
     fn load(mut file : File) -> Result<Option<Cache>, output::Message> {
         let mut raw_cache = String::new();
         let read = file.read_to_string(&mut raw_cache);
@@ -172,32 +323,79 @@ impl Cache {
     }
 }
 
+/// One profile in a richter directory: its enrollments, cache and local state.
+///
+/// ```no_run
+/// use richter::calendar::Calendar;
+/// use richter::profile::Profile;
+/// use richter::locations::Locations;
+///
+/// let locations = Locations::xdg().unwrap();
+/// let profile   = Profile::new(&locations, Some("alice")).unwrap();
+/// let cal = Calendar::load(&profile).unwrap();
+/// for (enrollment, entry) in cal.entries() {
+///     println!("{} {} {}", enrollment.label(), entry.due, entry.title);
+/// }
+/// ```
 pub struct Calendar {
-    path          : String                        ,
+    profile       : profile::Profile              ,
+    paths         : CalendarPaths                 ,
     enrollments   : Vec<enroll::Enrollment>       ,
     cache         : Option<Cache>                 ,
+    completion    : completion::Completion        ,
+    notes         : notes::Notes                  ,
 }
 
 struct CalendarPaths {
     enrollments   : PathBuf           ,
     cache         : PathBuf           ,
+    completion    : PathBuf           ,
+    notes         : PathBuf           ,
+    history       : PathBuf           ,
+    search        : PathBuf           ,
 }
 
 impl Calendar {
-    fn touch(path : &PathBuf) -> Result<CalendarPaths, output::Message> {
-        let mut cal  = (*path).clone();       
-        fs::create_dir_all(&cal)?;
-        cal.push("calendar");
-        cal.set_extension("yml");
+    fn touch(profile: &profile::Profile) -> Result<CalendarPaths, output::Message> {
+        let cal = profile.calendar_path();
+        if let Some(dir) = cal.parent() {
+            fs::create_dir_all(dir)?;
+        }
 
         OpenOptions::new().create(true).read(true).write(true).open(&cal)?;
 
-        let mut cache_path = (*path).clone();
+        let mut cache_path = profile.cache_dir();
+        fs::create_dir_all(&cache_path)?;
         cache_path.push(".cache");
 
         OpenOptions::new().create(true).read(true).write(true).open(&cache_path)?;
+
+        let mut search_path = profile.cache_dir();
+        search_path.push("search");
+        search_path.set_extension("json");
+
+        let data_dir = profile.data_dir();
+        fs::create_dir_all(&data_dir)?;
+
+        let mut completion_path = data_dir.clone();
+        completion_path.push("completed");
+        completion_path.set_extension("json");
+
+        OpenOptions::new().create(true).read(true).write(true).open(&completion_path)?;
+
+        let mut notes_path = data_dir.clone();
+        notes_path.push("notes");
+        notes_path.set_extension("json");
+
+        OpenOptions::new().create(true).read(true).write(true).open(&notes_path)?;
+
+        let mut history_path = data_dir.clone();
+        history_path.push("history");
+        history_path.set_extension("json");
+
+        OpenOptions::new().create(true).read(true).write(true).open(&history_path)?;
         
-        Ok(CalendarPaths {enrollments: cal, cache: cache_path})
+        Ok(CalendarPaths {enrollments: cal, cache: cache_path, completion: completion_path, notes: notes_path, history: history_path, search: search_path})
     }
 
     fn file_cache(path : &PathBuf) -> Result<Option<Cache>, output::Message> {
@@ -206,22 +404,77 @@ impl Calendar {
         let loaded = Cache::load(cache_file);
 
         match loaded  {
-            Err(msg)  => {println!("Deleting Corrupted Cache...");
-                          fs::remove_file(path);
+            Err(msg)  => {warn!("Deleting corrupted cache at {}", path.display());
+                          if let Err(err) = fs::remove_file(path) {
+                              warn!("Couldn't delete {}: {}", path.display(), err);
+                          }
                           Err(msg)},
 
             Ok(cache) =>  Ok(cache),
         }
     }
     
-    pub fn pull(path : &PathBuf) -> Result<Calendar, output::Message>{ 
-        let paths = Calendar::touch(path)?;
+    // Schools go to the file shared between profiles, entries to this profile's cache
+    fn store_pulled(profile: &profile::Profile, paths: &CalendarPaths, cache: &mut Cache) -> Result<(), output::Message> {
+        let mut shared = Calendar::shared_schools(profile)?;
+        for (school_id, sc) in cache.schools.drain() {
+            shared.insert(school_id, sc);
+        }
+        sidecar::save(&shared, &profile.schools_path(), "School Cache")?;
+        cache.dump(File::create(&paths.cache)?)?;
+        cache.schools = shared;
+        search::Index::build(cache).save(&paths.search)?;
+
+        // Both explicit pulls and those on a cache miss come through here, so notes are reconciled for either
+        let present = cache.entries.iter()
+                                   .flat_map(|&(ref enrollment, ref entries)| entries.iter().map(move |entry| smh::EntryKey::new(&enrollment.subdomain, entry.id)))
+                                   .collect();
+        let mut notes = notes::Notes::load(&paths.notes)?;
+        notes.mark_orphans(&present);
+        notes.save(&paths.notes)?;
+
+        let mut history = history::History::load(&paths.history)?;
+        for &(ref enrollment, ref entries) in cache.entries.iter() {
+            for entry in entries {
+                let teacher = cache.schools.get(&enrollment.school_id)
+                                           .and_then(|sc| sc.employees.get(&entry.employee_id))
+                                           .map_or(String::new(), |t| t.display_name());
+                history.record(&enrollment.subdomain, &teacher, entry);
+            }
+        }
+        history.save(&paths.history)
+    }
+
+    fn shared_schools(profile: &profile::Profile) -> Result<HashMap<i32, SchoolCache>, output::Message> {
+        let path = profile.schools_path();
+        OpenOptions::new().create(true).read(true).write(true).open(&path)?;
+        sidecar::load(&path, "School Cache")
+    }
+
+    // Caches from before profiles carry their own schools, the shared ones are newer
+    fn attach_schools(profile: &profile::Profile, cache: &mut Cache) -> Result<(), output::Message> {
+        for (school_id, sc) in Calendar::shared_schools(profile)? {
+            cache.schools.insert(school_id, sc);
+        }
+        Ok(())
+    }
+
+    /// Pulls a fresh cache from SMHW for the profile's enrollments, replacing the old one.
+    pub fn pull(profile: &profile::Profile) -> Result<Calendar, output::Message>{ 
+        let paths = Calendar::touch(profile)?;
         let enrollments = enroll::Enrollment::load(&paths.enrollments)?;
 
-        let cache = Cache::pull(&enrollments)?;
+        // An unreadable old cache only means fetching every entry's details again
+        let known = match Cache::load(File::open(&paths.cache)?) {
+            Ok (Some(old)) => old.homework,
+            _              => HashMap::new(),
+        };
+
+        let interface = interface::Interface::for_dir(&profile.data_dir())?;
+        let mut cache = Cache::pull(&interface, &enrollments, known)?;
         
-        if let Some(ref c) = cache {
-            c.dump(File::create(&paths.cache)?)?;
+        if let Some(ref mut c) = cache {
+            Calendar::store_pulled(profile, &paths, c)?;
         }
         else {
             return Err(output::Message::new("Pulling Cache",
@@ -229,16 +482,24 @@ impl Calendar {
                                             "No cache retrieved."));
         }
 
-        Ok(Calendar {path: (*path).to_str().unwrap().to_owned(), enrollments: enrollments, cache: cache})
+        let completion = completion::Completion::load(&paths.completion)?;
+        let notes      = notes::Notes::load(&paths.notes)?;
+
+        Ok(Calendar {profile: profile.clone(), paths: paths, enrollments: enrollments, cache: cache, completion: completion, notes: notes})
     }
 
-    fn load_any_cache(path: &PathBuf, enrollments: &Vec<enroll::Enrollment>) -> Result<Cache, output::Message> {
-        if let Some(cache) = Calendar::file_cache(path)? {
+    fn load_any_cache(profile: &profile::Profile, paths: &CalendarPaths, enrollments: &Vec<enroll::Enrollment>) -> Result<Cache, output::Message> {
+        let path = &paths.cache;
+        if let Some(mut cache) = Calendar::file_cache(path)? {
+            info!("Cache hit: loaded {} enrollments from {}", cache.entries.len(), path.display());
+            Calendar::attach_schools(profile, &mut cache)?;
             return Ok(cache);
         }
         else {
-            if let Some(c) = Cache::pull(enrollments)? {
-                c.dump(File::create(path)?)?;
+            info!("Cache miss: {} is empty, pulling from SMHW", path.display());
+            let interface = interface::Interface::for_dir(&profile.data_dir())?;
+            if let Some(mut c) = Cache::pull(&interface, enrollments, HashMap::new())? {
+                Calendar::store_pulled(profile, paths, &mut c)?;
                 return Ok(c);
             }
         }
@@ -247,13 +508,234 @@ impl Calendar {
                                  "Unable to obtain a cache"))
     }
 
-    pub fn load(path: &PathBuf) -> Result<Calendar, output::Message> {
-        let paths = Calendar::touch(path)?;
+    /// Loads the profile's calendar, only pulling from SMHW if there is no cache yet.
+    pub fn load(profile: &profile::Profile) -> Result<Calendar, output::Message> {
+        let paths = Calendar::touch(profile)?;
         let enrollments = enroll::Enrollment::load(&paths.enrollments)?;
-        let cache = Calendar::load_any_cache(&paths.cache, &enrollments)?;
-        Ok(Calendar {path: (*path).to_str().unwrap().to_owned(), enrollments: enrollments, cache: Some(cache)})
+        let cache = Calendar::load_any_cache(profile, &paths, &enrollments)?;
+        let completion = completion::Completion::load(&paths.completion)?;
+        let notes      = notes::Notes::load(&paths.notes)?;
+        Ok(Calendar {profile: profile.clone(), paths: paths, enrollments: enrollments, cache: Some(cache), completion: completion, notes: notes})
+    }
+
+    /// The profile this calendar belongs to.
+    pub fn profile(&self) -> &profile::Profile {
+        &self.profile
+    }
+
+    /// The enrollments read from `calendar.yml`.
+    pub fn enrollments(&self) -> &Vec<enroll::Enrollment> {
+        &self.enrollments
+    }
+
+    /// The cache of schools and entries, if one has been loaded or pulled.
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// Every cached entry with its enrollment, soonest due first
+    pub fn entries(&self) -> Vec<(&enroll::Enrollment, &smh::Entry)> {
+        let mut listed = vec![];
+        if let Some(ref cache) = self.cache {
+            for &(ref enrollment, ref entries) in &cache.entries {
+                for entry in entries {
+                    listed.push((enrollment, entry));
+                }
+            }
+        }
+        listed.sort_by(|a, b| (&a.1.due, a.1.id).cmp(&(&b.1.due, b.1.id)));
+        listed
+    }
+
+    /// The full homework record for an entry, if it came through on the last pull.
+    pub fn homework(&self, enrollment: &enroll::Enrollment, entry: &smh::Entry) -> Option<&smh::Homework> {
+        self.cache.as_ref()
+                  .and_then(|c| c.homework.get(&enrollment.subdomain))
+                  .and_then(|by_id| by_id.get(&entry.id))
+    }
+
+    /// The teacher who set an entry, looked up in the school's employees.
+    pub fn teacher(&self, enrollment: &enroll::Enrollment, entry: &smh::Entry) -> Option<&smh::Employee> {
+        self.cache.as_ref()
+                  .and_then(|c| c.schools.get(&enrollment.school_id))
+                  .and_then(|sc| sc.employees.get(&entry.employee_id))
+    }
+
+    /// Entries matching the words in `text`, best first. Among equally good
+    /// matches, ones due nearer today (before or after) come first.
+    pub fn search(&self, text: &str) -> Result<Vec<(&enroll::Enrollment, &smh::Entry)>, output::Message> {
+        let mut index = search::Index::load(&self.paths.search)?;
+        if index.is_empty() {
+            // Caches pulled before there was an index
+            if let Some(ref cache) = self.cache {
+                index = search::Index::build(cache);
+                index.save(&self.paths.search)?;
+            }
+        }
+
+        let entries = self.entries();
+        let mut found: Vec<(f64, usize, &enroll::Enrollment, &smh::Entry)> = vec![];
+        for hit in index.search(text) {
+            let matching = entries.iter().find(|&&(e, entry)| e.subdomain == hit.key.subdomain && entry.id == hit.key.id);
+            if let Some(&(enrollment, entry)) = matching {
+                let distance = entry.days_until_due().map_or(365, |d| d.abs()) as f64;
+                let recency  = 1.0 / (1.0 + distance / 60.0);
+                found.push((hit.relevance * (1.0 + recency), hit.matched, enrollment, entry));
+            }
+        }
+        found.sort_by(|a, b| (b.1, b.0).partial_cmp(&(a.1, a.0)).unwrap_or(Ordering::Equal));
+        Ok(found.into_iter().map(|(_, _, enrollment, entry)| (enrollment, entry)).collect())
+    }
+
+    /// Overloaded days and weeks and short-notice entries, against the limits
+    /// in `calendar.yml`. With `from`, only those on or after that day.
+    pub fn clashes(&self, from: Option<&time::Tm>) -> Result<Vec<clashes::Clash>, output::Message> {
+        let thresholds = clashes::Thresholds::load(&self.paths.enrollments)?;
+        let entries: Vec<&smh::Entry> = self.entries().into_iter().map(|(_, entry)| entry).collect();
+        Ok(clashes::find(&thresholds, &entries, from))
+    }
+
+    /// Everything a `query::Query` can ask about an entry.
+    pub fn facts<'a>(&self, enrollment: &enroll::Enrollment, entry: &'a smh::Entry) -> query::Facts<'a> {
+        let teacher = self.teacher(enrollment, entry).map_or(String::new(), |t| t.display_name());
+        self.facts_for(&enrollment.subdomain, teacher, entry)
+    }
+
+    /// Every entry ever pulled into this calendar, including those gone from the cache.
+    pub fn history(&self) -> Result<history::History, output::Message> {
+        history::History::load(&self.paths.history)
+    }
+
+    /// `facts` for an entry from `history`, with the teacher as it was when pulled.
+    pub fn recorded_facts<'a>(&self, recorded: &'a history::Recorded) -> query::Facts<'a> {
+        self.facts_for(&recorded.subdomain, recorded.teacher.clone(), &recorded.entry)
+    }
+
+    fn facts_for<'a>(&self, subdomain: &str, teacher: String, entry: &'a smh::Entry) -> query::Facts<'a> {
+        let key        = smh::EntryKey::new(subdomain, entry.id);
+        let annotation = self.notes.get(&key);
+        query::Facts {entry  : entry,
+                      teacher: teacher,
+                      done   : self.completion.is_done(&key),
+                      tags   : annotation.map_or(vec![], |a| a.tags.iter().cloned().collect()),
+                      notes  : annotation.map_or(vec![], |a| a.notes.clone()),
+                      child  : self.profile.label().to_owned(),}
     }
 
+    /// Whether the entry has been marked done locally.
+    pub fn is_done(&self, enrollment: &enroll::Enrollment, entry: &smh::Entry) -> bool {
+        self.completion.is_done(&smh::EntryKey::new(&enrollment.subdomain, entry.id))
+    }
+
+    // Every cached entry with this id, there is normally only the one
+    fn keys_for(&self, id: i32) -> Result<Vec<smh::EntryKey>, output::Message> {
+        let keys: Vec<smh::EntryKey> = self.entries()
+                                           .iter()
+                                           .filter(|&&(_, entry)| entry.id == id)
+                                           .map(|&(enrollment, entry)| smh::EntryKey::new(&enrollment.subdomain, entry.id))
+                                           .collect();
+        if keys.is_empty() {
+            return Err(output::Message::new("Calendar",
+                                            "Looking up Entry",
+                                            &format!("No cached entry with id {}", id)));
+        }
+        Ok(keys)
+    }
+
+    /// Marks the entry with this id as done or not done.
+    pub fn set_done(&mut self, id: i32, done: bool) -> Result<(), output::Message> {
+        for key in self.keys_for(id)? {
+            self.completion.set_done(key, done);
+        }
+        self.completion.save(&self.paths.completion)
+    }
+
+    /// The notes and tags attached to an entry, if any.
+    pub fn annotation(&self, enrollment: &enroll::Enrollment, entry: &smh::Entry) -> Option<&notes::Annotation> {
+        self.notes.get(&smh::EntryKey::new(&enrollment.subdomain, entry.id))
+    }
+
+    /// Every annotation, including orphans whose entry has gone upstream.
+    pub fn annotations(&self) -> &Vec<notes::Annotation> {
+        self.notes.annotations()
+    }
+
+    /// Attaches a free text note to the entry with this id.
+    pub fn add_note(&mut self, id: i32, note: &str) -> Result<(), output::Message> {
+        for key in self.keys_for(id)? {
+            self.notes.add_note(key, note);
+        }
+        self.notes.save(&self.paths.notes)
+    }
+
+    /// Tags the entry with this id, a leading `#` is optional.
+    pub fn add_tags(&mut self, id: i32, tags: &[&str]) -> Result<(), output::Message> {
+        for key in self.keys_for(id)? {
+            for tag in tags {
+                self.notes.add_tag(key.clone(), tag);
+            }
+        }
+        self.notes.save(&self.paths.notes)
+    }
+
+    /// Removes tags from the entry with this id.
+    pub fn remove_tags(&mut self, id: i32, tags: &[&str]) -> Result<(), output::Message> {
+        for key in self.keys_for(id)? {
+            for tag in tags {
+                self.notes.remove_tag(key.clone(), tag);
+            }
+        }
+        self.notes.save(&self.paths.notes)
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn entry(id: i32, title: &str) -> smh::Entry {
+        smh::Entry {id: id, title: title.to_owned(), class_name: "10A/Ma1".to_owned(), year_name: "Year 10".to_owned(),
+                    subject_name: "Maths".to_owned(), employee_id: 7, issued: "2017-02-13T00:00:00.000Z".to_owned(),
+                    due: "2017-02-20T00:00:00.000Z".to_owned()}
+    }
+
+    #[test]
+    fn cache_survives_a_dump_and_load() {
+        let rule = enroll::Rule {class: Some("10A/Ma1".to_owned()), ..enroll::Rule::default()};
+        let enrollment = enroll::Enrollment {subdomain: "hill".to_owned(), school_id: 3, rule: rule, except: vec![]};
+
+        let mut cache = Cache::new();
+        cache.add_entries(enrollment, vec![entry(1, "Fractions"), entry(2, "Algebra")]);
+
+        let mut path = env::temp_dir();
+        path.push(format!("richter-cache-{}.json", ::std::process::id()));
+        cache.dump(File::create(&path).unwrap()).unwrap();
+        let loaded = Cache::load(File::open(&path).unwrap()).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.entries.len(), 1);
+        let (ref enrollment, ref entries) = loaded.entries[0];
+        assert_eq!(enrollment.subdomain, "hill");
+        assert_eq!(enrollment.rule.class, Some("10A/Ma1".to_owned()));
+        assert_eq!(entries.iter().map(|e| e.title.as_str()).collect::<Vec<&str>>(), vec!["Fractions", "Algebra"]);
+    }
+
+    #[test]
+    fn edit_distance_counts_chars() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("maths", ""), 5);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("Französisch", "Franzosisch"), 1);
+    }
 
+    #[test]
+    fn suggest_prefers_case_then_closest() {
+        let known: Vec<String> = ["Maths", "MATHS", "Music", "Physics", "Geography"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(suggest("maths", known.iter()), vec!["Maths", "MATHS"]);
+        assert_eq!(suggest("Physcs", known.iter()), vec!["Physics"]);
+        assert_eq!(suggest("Mathz", known.iter()), vec!["MATHS", "Maths"]);
+        assert_eq!(suggest("Geografy", known.iter()), vec!["Geography"]);
+        assert!(suggest("Chemistry", known.iter()).is_empty());
+    }
+}
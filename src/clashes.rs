@@ -0,0 +1,194 @@
+//! Days and weeks with too much homework due, and homework set at short notice.
+//!
+//! The limits come from the optional `clashes` section of `calendar.yml`:
+//!
+//! ```yaml
+//! clashes:
+//!   per_day: 3       # More than this due on one day is a clash
+//!   per_week: 10     # Likewise for a week, Monday to Sunday
+//!   min_lead_days: 2 # Less time than this between issue and due is short notice
+//! ```
+
+extern crate yaml_rust;
+extern crate time;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use self::yaml_rust::{YamlLoader, Yaml};
+
+use smh;
+use output;
+
+/// Limits for one child's homework.
+#[derive(Debug, Clone)]
+pub struct Thresholds {
+    pub per_day       : usize,
+    pub per_week      : usize,
+    pub min_lead_days : i64  ,
+}
+
+impl Default for Thresholds {
+    fn default() -> Thresholds {
+        Thresholds {per_day: 3, per_week: 10, min_lead_days: 2}
+    }
+}
+
+fn limit(section: &Yaml, key: &str, default: usize) -> Result<usize, output::Message> {
+    match section[key] {
+        Yaml::Integer(n) if n > 0 => Ok(n as usize),
+        Yaml::BadValue            => Ok(default)   ,
+        _                         => Err(output::Message::new("Reading YAML File",
+                                                              "Loading Clash Limits",
+                                                              &format!("`{}` is not a positive Integer", key))),
+    }
+}
+
+impl Thresholds {
+    /// Reads the `clashes` section, anything left out keeps its default.
+    pub fn load(path: &PathBuf) -> Result<Thresholds, output::Message> {
+        let mut f = File::open(path)?;
+        let mut raw = String::new();
+        f.read_to_string(&mut raw)?;
+
+        let mut thresholds = Thresholds::default();
+        let docs = YamlLoader::load_from_str(&raw)?;
+        let section = match docs.first() {
+            Some(doc) => &doc["clashes"],
+            None      => return Ok(thresholds),
+        };
+        if section.is_badvalue() {
+            return Ok(thresholds);
+        }
+
+        thresholds.per_day  = limit(section, "per_day" , thresholds.per_day )?;
+        thresholds.per_week = limit(section, "per_week", thresholds.per_week)?;
+        match section["min_lead_days"] {
+            Yaml::Integer(n) if n >= 0 => thresholds.min_lead_days = n,
+            Yaml::BadValue             => {},
+            _                          => return Err(output::Message::new("Reading YAML File",
+                                                                          "Loading Clash Limits",
+                                                                          "`min_lead_days` is not a whole number of days")),
+        }
+        Ok(thresholds)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Day        ,
+    Week       ,
+    ShortNotice,
+}
+
+/// One problem, dated by the day (or the Monday of the week) it's about.
+#[derive(Debug, Clone)]
+pub struct Clash {
+    pub kind    : Kind    ,
+    pub date    : String  , // YYYY-MM-DD
+    pub ids     : Vec<i32>, // The entries involved
+    pub message : String  ,
+}
+
+/// Every clash among one child's entries, in date order. With `from`, only
+/// clashes on or after that day are kept.
+pub fn find(thresholds: &Thresholds, entries: &[&smh::Entry], from: Option<&time::Tm>) -> Vec<Clash> {
+    let mut days  : BTreeMap<String, Vec<i32>> = BTreeMap::new();
+    let mut weeks : BTreeMap<String, Vec<i32>> = BTreeMap::new();
+    let mut found = vec![];
+
+    let earliest = from.map(|day| smh::format_day("%Y-%m-%d", day));
+    for entry in entries {
+        let due = match entry.due_date() {
+            Some(due) => due,
+            None      => continue,
+        };
+        let date   = smh::format_day("%Y-%m-%d", &due);
+        let monday = smh::week_start(&due);
+        let sunday = smh::format_day("%Y-%m-%d", &smh::days_after(&monday, 6));
+
+        // A week that started before `from` still counts until it's over
+        if earliest.as_ref().map_or(false, |e| *e > sunday) {
+            continue;
+        }
+        weeks.entry(smh::format_day("%Y-%m-%d", &monday)).or_insert(vec![]).push(entry.id);
+        if earliest.as_ref().map_or(false, |e| *e > date) {
+            continue;
+        }
+        days.entry(date.clone()).or_insert(vec![]).push(entry.id);
+
+        if let Some(issued) = entry.issued_date() {
+            let lead = (due - issued).num_days();
+            if lead < thresholds.min_lead_days {
+                found.push(Clash {kind   : Kind::ShortNotice,
+                                  date   : date,
+                                  ids    : vec![entry.id],
+                                  message: format!("`{}` was due {} day{} after it was set", entry.title, lead, if lead == 1 { "" } else { "s" })});
+            }
+        }
+    }
+
+    for (date, ids) in days {
+        if ids.len() > thresholds.per_day {
+            let message = format!("{} pieces of homework due on one day, the limit is {}", ids.len(), thresholds.per_day);
+            found.push(Clash {kind: Kind::Day, date: date, ids: ids, message: message});
+        }
+    }
+    for (monday, ids) in weeks {
+        if ids.len() > thresholds.per_week {
+            let message = format!("{} pieces of homework due in one week, the limit is {}", ids.len(), thresholds.per_week);
+            found.push(Clash {kind: Kind::Week, date: monday, ids: ids, message: message});
+        }
+    }
+
+    found.sort_by(|a, b| a.date.cmp(&b.date));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: i32, issued: &str, due: &str) -> smh::Entry {
+        smh::Entry {id: id, title: format!("Homework {}", id), class_name: String::new(), year_name: String::new(),
+                    subject_name: String::new(), employee_id: 0,
+                    issued: format!("{}T00:00:00.000Z", issued), due: format!("{}T00:00:00.000Z", due)}
+    }
+
+    fn kinds(clashes: &[Clash]) -> Vec<(Kind, &str)> {
+        clashes.iter().map(|c| (c.kind, c.date.as_str())).collect()
+    }
+
+    #[test]
+    fn limits_are_exclusive() {
+        // Monday 20 February 2017 has three due, the Wednesday one was set the day before
+        let entries = vec![entry(1, "2017-02-13", "2017-02-20"), entry(2, "2017-02-13", "2017-02-20"),
+                           entry(3, "2017-02-13", "2017-02-20"), entry(4, "2017-02-21", "2017-02-22")];
+        let refs: Vec<&smh::Entry> = entries.iter().collect();
+
+        let tight = Thresholds {per_day: 2, per_week: 3, min_lead_days: 2};
+        assert_eq!(kinds(&find(&tight, &refs, None)),
+                   vec![(Kind::Day, "2017-02-20"), (Kind::Week, "2017-02-20"), (Kind::ShortNotice, "2017-02-22")]);
+
+        let exact = Thresholds {per_day: 3, per_week: 4, min_lead_days: 1};
+        assert!(find(&exact, &refs, None).is_empty());
+    }
+
+    #[test]
+    fn from_keeps_the_week_it_falls_in() {
+        let entries = vec![entry(1, "2017-02-13", "2017-02-20"), entry(2, "2017-02-13", "2017-02-20"),
+                           entry(3, "2017-02-13", "2017-02-20"), entry(4, "2017-02-21", "2017-02-22")];
+        let refs: Vec<&smh::Entry> = entries.iter().collect();
+        let tight = Thresholds {per_day: 2, per_week: 3, min_lead_days: 2};
+
+        let tuesday = smh::parse_date("2017-02-21").unwrap();
+        let found   = find(&tight, &refs, Some(&tuesday));
+        assert_eq!(kinds(&found), vec![(Kind::Week, "2017-02-20"), (Kind::ShortNotice, "2017-02-22")]);
+        assert_eq!(found[0].ids, vec![1, 2, 3, 4]);
+
+        let next_week = smh::parse_date("2017-02-27").unwrap();
+        assert!(find(&tight, &refs, Some(&next_week)).is_empty());
+    }
+}
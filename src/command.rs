@@ -30,15 +30,62 @@ impl Command {
         }
         panic!("Error in Command Parser: No Verb")
     }
+
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.count_flag(name) > 0
+    }
+
+    // Repeatable flags like `-vv` show up once per repetition
+    pub fn count_flag(&self, name: &str) -> usize {
+        self.args.iter().filter(|arg| match *arg {
+            &Argument::Flag(ref f) => f == name,
+            _                      => false    ,
+        }).count()
+    }
+
+    pub fn get_args(&self) -> Vec<&str> {
+        let mut positional = vec![];
+        for arg in self.args.iter() {
+            if let &Argument::Arg(Value::StringVal(ref s)) = arg {
+                positional.push(s.as_str());
+            }
+        }
+        positional
+    }
+
+    pub fn get_opt(&self, name: &str) -> Option<&str> {
+        for arg in self.args.iter() {
+            if let &Argument::Opt(ref o, Value::StringVal(ref s)) = arg {
+                if o == name {
+                    return Some(s);
+                }
+            }
+        }
+        None
+    }
 }
 
 enum ParseExpect {
-    NextVerb          ,
     NextVal  (String) ,
     New               ,
 }
 
-pub fn parse_args(verbs: Vec<String>, raw_args: Vec<String>) -> Result<Command, String> {
+// Pushes a switch that might take a value: known valueless flags never do
+fn push_switch(args: &mut Vec<Argument>, flags: &[String], name: String) -> ParseExpect {
+    if flags.contains(&name) {
+        args.push(Argument::Flag(name));
+        ParseExpect::New
+    }
+    else {
+        ParseExpect::NextVal(name)
+    }
+}
+
+/// Parses `raw_args` (binary path first) into a Command. The verb is the first
+/// bare argument that isn't an option's value, so switches may come before it,
+/// as in `richter -v pull`. Switches named in `flags` never take a value, so
+/// `list --no-color @maths` keeps `@maths` as an argument.
+pub fn parse_args(verbs: Vec<String>, flags: Vec<String>, raw_args: Vec<String>) -> Result<Command, String> {
     if raw_args.len() == 0 {
         return Err(String::from("No arguments to parse."));
     }
@@ -49,20 +96,18 @@ pub fn parse_args(verbs: Vec<String>, raw_args: Vec<String>) -> Result<Command,
     // Create a Vec for our parsed command Arguments (of type Argument)
     let mut args = Vec::new();
     
-    // We begin by expecting a Verb - if it's anywhere, it has to be at the start
-    let mut expect = ParseExpect::NextVerb;
+    // The first bare argument decides the Verb, until then we're still looking
+    let mut verb_found = false;
+    let mut expect     = ParseExpect::New;
 
     'args: for raw in &raw_args[1..] {
 
         // Any flags or options begin with '-' (might be just a single or a double)
         if raw.starts_with("-") {
 
-            // If we haven't yet had a Verb, we aren't getting one
             // The preceding switch must be a flag not an option because it's not getting a value
-            match expect {
-                ParseExpect::NextVerb          => args.push(Argument::Verb(None))         ,
-                ParseExpect::NextVal(ref flag) => args.push(Argument::Flag(flag.clone())) ,
-                ParseExpect::New               => {}                                      ,
+            if let ParseExpect::NextVal(ref flag) = expect {
+                args.push(Argument::Flag(flag.clone()));
             }
 
             // We're starting a new switch, we want the Parser to be in a clean state
@@ -90,8 +135,8 @@ pub fn parse_args(verbs: Vec<String>, raw_args: Vec<String>) -> Result<Command,
                     long_opt.push(opt_char);
                 }
 
-                // We might still get a value in the next arg, so we expect a NextVal
-                expect = ParseExpect::NextVal(long_opt);
+                // We might still get a value in the next arg, unless it's a known flag
+                expect = push_switch(&mut args, &flags, long_opt);
             }
             // Handle short switches (-xkcd is -x -k -c -d)
             else {
@@ -115,13 +160,12 @@ pub fn parse_args(verbs: Vec<String>, raw_args: Vec<String>) -> Result<Command,
                     }
                     else {
                         // If there's no value, then the NextVal gets converted into a valueless flag
-                        match expect {
-                            ParseExpect::NextVal(flag) => args.push(Argument::Flag(flag.clone())) ,
-                            _                          => {},
+                        if let ParseExpect::NextVal(flag) = expect {
+                            args.push(Argument::Flag(flag));
                         }
                         
                         // Move on to the next switch
-                        expect = ParseExpect::NextVal(opt_char.to_string());
+                        expect = push_switch(&mut args, &flags, opt_char.to_string());
                     }
                 }
             }
@@ -134,18 +178,19 @@ pub fn parse_args(verbs: Vec<String>, raw_args: Vec<String>) -> Result<Command,
             // Whatever we're doing, we want a heap allocated copy of this raw argument
             let arg_val = raw.to_owned();
             
-            // If we expect a verb and raw is a valid verb, we create an Argument::Verb
-            // Otherwise, it becomes a plain old Arg
             // If we're expecting a value, we create an Opt from the existing switch
-            // If we're in a clean state (New) then we are free to create a plain Arg
+            // Otherwise the first bare argument is the Verb if it's a valid one,
+            // and anything else is a plain old Arg
             match expect {
-                ParseExpect::NextVerb     => if verbs.contains(raw) {
-                                                 args.push(Argument::Verb(Some(arg_val.to_lowercase()))) 
-                                             } else {
-                                                 args.push(Argument::Arg(Value::StringVal(arg_val)))
-                                             },
-                ParseExpect::NextVal(opt) => args.push(Argument::Opt(opt, Value::StringVal(arg_val)))   ,
-                ParseExpect::New          => args.push(Argument::Arg(Value::StringVal(arg_val)))        ,
+                ParseExpect::NextVal(opt)           => args.push(Argument::Opt(opt, Value::StringVal(arg_val))),
+                ParseExpect::New if !verb_found     => {verb_found = true;
+                                                        if verbs.contains(raw) {
+                                                            args.push(Argument::Verb(Some(arg_val.to_lowercase())))
+                                                        } else {
+                                                            args.push(Argument::Verb(None));
+                                                            args.push(Argument::Arg(Value::StringVal(arg_val)))
+                                                        }},
+                ParseExpect::New                    => args.push(Argument::Arg(Value::StringVal(arg_val))),
             }
 
             // We're expecting nothing so we clean state
@@ -155,14 +200,57 @@ pub fn parse_args(verbs: Vec<String>, raw_args: Vec<String>) -> Result<Command,
     }
     
     // We need to clean up any left over expectancies from after the loop
-    // If we're still expecting a Verb (i.e. no args) we create a None value one
+    // If we never found a Verb (i.e. no bare args) we create a None value one
     // If we're still expecting a Value, we turn the switch into a Flag
-    match expect {
-        ParseExpect::NextVerb      => args.push(Argument::Verb(None)),
-        ParseExpect::NextVal(flag) => args.push(Argument::Flag(flag)),
-        ParseExpect::New           => {}                             ,
+    if !verb_found {
+        args.push(Argument::Verb(None));
+    }
+    if let ParseExpect::NextVal(flag) = expect {
+        args.push(Argument::Flag(flag));
     }
     
     // Return the resultant parsed Command
     Ok(Command {path: path, args: args})
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &[&str]) -> Command {
+        let verbs = vec!["list".to_owned(), "pull".to_owned()];
+        let flags = vec!["no-color".to_owned(), "v".to_owned()];
+        let raw   = Some("richter").into_iter().chain(raw.iter().cloned()).map(|a| a.to_owned()).collect();
+        parse_args(verbs, flags, raw).unwrap()
+    }
+
+    #[test]
+    fn valueless_flags_leave_the_next_argument() {
+        let command = parse(&["list", "--no-color", "@maths"]);
+        assert!(command.has_flag("no-color"));
+        assert_eq!(command.get_args(), vec!["@maths"]);
+    }
+
+    #[test]
+    fn options_still_take_values() {
+        let command = parse(&["list", "--where", "done", "--profile=alice"]);
+        assert_eq!(command.get_opt("where"), Some("done"));
+        assert_eq!(command.get_opt("profile"), Some("alice"));
+        assert!(command.get_args().is_empty());
+    }
+
+    #[test]
+    fn switches_may_come_before_the_verb() {
+        let command = parse(&["-vv", "--profile", "alice", "pull"]);
+        assert_eq!(command.get_verb(), &Some("pull".to_owned()));
+        assert_eq!(command.count_flag("v"), 2);
+        assert_eq!(command.get_opt("profile"), Some("alice"));
+    }
+
+    #[test]
+    fn unknown_verb_is_none() {
+        let command = parse(&["frobnicate", "x"]);
+        assert_eq!(command.get_verb(), &None);
+        assert_eq!(command.get_args(), vec!["frobnicate", "x"]);
+    }
+}
@@ -0,0 +1,38 @@
+//! Local done/not-done tracking for entries.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use smh;
+use output;
+use sidecar;
+
+/// Done/not-done state for entries. This lives in its own file next to the
+/// cache so that a pull, which rewrites `.cache`, never loses it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Completion {
+    done : BTreeSet<smh::EntryKey>,
+}
+
+impl Completion {
+    pub fn load(path: &PathBuf) -> Result<Completion, output::Message> {
+        sidecar::load(path, "Completion")
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), output::Message> {
+        sidecar::save(self, path, "Completion")
+    }
+
+    pub fn is_done(&self, key: &smh::EntryKey) -> bool {
+        self.done.contains(key)
+    }
+
+    pub fn set_done(&mut self, key: smh::EntryKey, done: bool) {
+        if done {
+            self.done.insert(key);
+        }
+        else {
+            self.done.remove(&key);
+        }
+    }
+}
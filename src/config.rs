@@ -0,0 +1,506 @@
+//! Checks a whole `calendar.yml` at once, with a line and column for each problem.
+//!
+//! `enroll` and `reminder` stop at the first thing they can't use, this
+//! keeps going so `richter config check` can list everything in one run.
+
+extern crate yaml_rust;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use self::yaml_rust::parser::{Parser, Event, MarkedEventReceiver};
+use self::yaml_rust::scanner::{Marker, TScalarStyle};
+
+use output;
+use reminder;
+use query;
+
+type Pos = (usize, usize); // Line and column, both from 1
+
+/// One problem found in a `calendar.yml`.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub line    : usize ,
+    pub column  : usize ,
+    pub message : String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+// Markers count lines from 1 but columns from 0
+fn position(mark: &Marker) -> Pos {
+    (mark.line(), mark.col() + 1)
+}
+
+// A YAML document that remembers where everything was, and keeps duplicate keys
+#[derive(Debug)]
+enum Node {
+    Scalar (String, bool, Pos)       , // The bool is whether it was unquoted
+    Seq    (Vec<Node>, Pos)          ,
+    Map    (Vec<(Node, Node)>, Pos)  ,
+    Alias  (Pos)                     ,
+}
+
+impl Node {
+    fn pos(&self) -> Pos {
+        match *self {
+            Node::Scalar(_, _, p) => p,
+            Node::Seq   (_, p)    => p,
+            Node::Map   (_, p)    => p,
+            Node::Alias (p)       => p,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match *self {
+            Node::Scalar(ref s, _, _) => Some(s),
+            _                         => None   ,
+        }
+    }
+
+    // Only unquoted numbers are integers to yaml-rust, `"12"` is a string
+    fn as_int(&self) -> Option<i64> {
+        match *self {
+            Node::Scalar(ref s, true, _) => s.parse().ok(),
+            _                            => None          ,
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        match *self {
+            Node::Scalar(ref s, true, _) => s == "~" || s == "null" || s.is_empty(),
+            _                            => false                                   ,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    stack : Vec<Node>              ,
+    keys  : Vec<Option<Node>>      , // A key waiting for its value, one per open mapping
+    root  : Option<Node>           ,
+    broken: Option<output::Message>, // Events the parser shouldn't have sent, reported after loading
+}
+
+impl Builder {
+    fn insert(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            None                                             => if self.root.is_none() { self.root = Some(node) },
+            Some(&mut Node::Seq(ref mut items, _))           => items.push(node),
+            Some(&mut Node::Map(ref mut pairs, ref mut pos)) => {
+                let waiting = match self.keys.last_mut() {
+                    Some(waiting) => waiting,
+                    None          => {self.broken = Some(output::Message::new("Checking Config",
+                                                                              "Building YAML tree",
+                                                                              "Mapping without a key slot"));
+                                      return},
+                };
+                match waiting.take() {
+                    Some(key) => pairs.push((key, node)),
+                    None      => {// A block mapping is marked after its first key, point at that key instead
+                                  if pairs.is_empty() { *pos = node.pos() }
+                                  *waiting = Some(node)},
+                }
+            },
+            Some(_)                                          => {},
+        }
+    }
+}
+
+impl MarkedEventReceiver for Builder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        let pos = position(&mark);
+        match ev {
+            Event::Scalar(v, style, _, _)     => self.insert(Node::Scalar(v, style == TScalarStyle::Plain, pos)),
+            Event::Alias(_)                   => self.insert(Node::Alias(pos)),
+            Event::SequenceStart(_)           => self.stack.push(Node::Seq(vec![], pos)),
+            Event::MappingStart(_)            => {self.stack.push(Node::Map(vec![], pos));
+                                                  self.keys.push(None)},
+            Event::SequenceEnd                => if let Some(node) = self.stack.pop() { self.insert(node) },
+            Event::MappingEnd                 => {self.keys.pop();
+                                                  if let Some(node) = self.stack.pop() { self.insert(node) }},
+            _                                 => {},
+        }
+    }
+}
+
+struct Checker {
+    found : Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn report(&mut self, pos: Pos, message: &str) {
+        self.found.push(Diagnostic {line: pos.0, column: pos.1, message: message.to_owned()});
+    }
+
+    // Reports unknown and repeated keys, and hands back each known key's (last) value
+    fn fields<'a>(&mut self, pairs: &'a [(Node, Node)], known: &[&str], what: &str) -> HashMap<String, &'a Node> {
+        let mut seen: HashMap<String, &'a Node> = HashMap::new();
+        for &(ref key, ref value) in pairs {
+            let name = match key.as_str() {
+                Some(name) => name,
+                None       => {self.report(key.pos(), &format!("{} key is not a string", what));
+                               continue},
+            };
+            if !known.contains(&name) {
+                self.report(key.pos(), &format!("unknown key `{}` in {}, expected one of: {}", name, what, known.join(", ")));
+            }
+            else if seen.contains_key(name) {
+                self.report(key.pos(), &format!("duplicate key `{}` in {}, only this one is used", name, what));
+            }
+            seen.insert(name.to_owned(), value);
+        }
+        seen
+    }
+
+    fn string(&mut self, node: Option<&&Node>, at: Pos, what: &str) -> Option<String> {
+        match node {
+            None                                => {self.report(at, &format!("missing {}", what)); None},
+            Some(n) if n.is_null()              => {self.report(n.pos(), &format!("{} is empty", what)); None},
+            Some(n) if n.as_int().is_some()     => {self.report(n.pos(), &format!("{} should be a string, put it in quotes", what)); None},
+            Some(n)                             => match n.as_str() {
+                Some(s) => Some(s.to_owned()),
+                None    => {self.report(n.pos(), &format!("{} is not a string", what)); None},
+            },
+        }
+    }
+
+    // Returns every school name defined, even ones with other problems, so enrollments can be checked against them
+    fn schools(&mut self, schools: Option<&&Node>, at: Pos) -> Vec<String> {
+        let pairs = match schools {
+            Some(&&Node::Map(ref pairs, _)) => pairs,
+            Some(n)                         => {self.report(n.pos(), "`schools` is not a mapping of school names to an id and subdomain");
+                                                return vec![]},
+            None                            => {self.report(at, "missing `schools` section");
+                                                return vec![]},
+        };
+
+        let mut names = vec![];
+        for &(ref key, ref info) in pairs {
+            let name = match key.as_str() {
+                Some(name) => name.to_owned(),
+                None       => {self.report(key.pos(), "school name is not a string");
+                               continue},
+            };
+            if names.contains(&name) {
+                self.report(key.pos(), &format!("school `{}` is defined twice, only this one is used", name));
+            }
+
+            let info_pairs = match *info {
+                Node::Map(ref p, _) => p,
+                _                   => {self.report(info.pos(), &format!("school `{}` should have an `id` and a `subdomain`", name));
+                                        names.push(name);
+                                        continue},
+            };
+            let fields = self.fields(info_pairs, &["id", "subdomain"], &format!("school `{}`", name));
+            let valid_id = |i: i64| i > 0 && i <= i32::max_value() as i64;
+            match fields.get("id") {
+                None                                                              => self.report(info.pos(), &format!("school `{}` has no `id`", name)),
+                Some(id) if id.as_int().map_or(false, &valid_id)                  => {},
+                Some(&&Node::Scalar(ref i, false, p)) if i.parse::<i32>().is_ok() => self.report(p, &format!("id for school `{}` is quoted, so it's read as a string", name)),
+                Some(id)                                                          => self.report(id.pos(), &format!("malformed id `{}` for school `{}`, expected a positive whole number",
+                                                                                                                    id.as_str().unwrap_or("..."), name)),
+            }
+            self.string(fields.get("subdomain"), info.pos(), &format!("`subdomain` for school `{}`", name));
+            names.push(name);
+        }
+        names
+    }
+
+    fn enrollments(&mut self, enrollments: Option<&&Node>, at: Pos, schools: &[String]) {
+        let items = match enrollments {
+            Some(&&Node::Seq(ref items, _)) => items,
+            Some(n)                         => return self.report(n.pos(), "`enrollments` is not a list"),
+            None                            => return self.report(at, "missing `enrollments` list"),
+        };
+
+        let mut seen: HashMap<(String, String), Pos> = HashMap::new();
+        for item in items {
+            let pairs = match *item {
+                Node::Map(ref p, _) => p,
+                _                   => {self.report(item.pos(), "enrollment should be a mapping with a `school` and a `class`, `year`, `subject` or `pattern`");
+                                        continue},
+            };
+            let fields = self.fields(pairs, &["school", "class", "year", "subject", "pattern", "except"], "enrollment");
+            let school = self.string(fields.get("school"), item.pos(), "`school` for enrollment");
+            let rule   = self.rule(&fields, item.pos(), "enrollment");
+
+            let except: Vec<String> = match fields.get("except") {
+                Some(&&Node::Seq(ref rules, _)) => rules.iter().filter_map(|except| self.exception(except)).collect(),
+                Some(except)                    => self.exception(except).into_iter().collect(),
+                None                            => vec![],
+            };
+            let rule = match except.is_empty() {
+                true  => rule,
+                false => rule.map(|r| format!("{} except {}", r, except.join(" or "))),
+            };
+
+            if let (Some(school), Some(pos)) = (school.as_ref(), fields.get("school").map(|n| n.pos())) {
+                if !schools.contains(school) {
+                    self.report(pos, &format!("enrollment refers to undefined school `{}`", school));
+                }
+            }
+            if let (Some(school), Some(rule)) = (school, rule) {
+                let key = (school, rule);
+                if let Some(first) = seen.get(&key) {
+                    self.report(item.pos(), &format!("duplicate enrollment in `{}` at `{}`, first at line {}", key.1, key.0, first.0));
+                    continue;
+                }
+                seen.insert(key, item.pos());
+            }
+        }
+    }
+
+    // The class, year, subject and pattern of an enrollment or exception, described as `enroll::Rule::label` would
+    fn rule(&mut self, fields: &HashMap<String, &Node>, at: Pos, what: &str) -> Option<String> {
+        let mut parts = vec![];
+        for name in ["class", "year", "subject", "pattern"].iter() {
+            if let Some(node) = fields.get(*name) {
+                if let Some(part) = self.string(Some(node), at, &format!("`{}` for {}", name, what)) {
+                    parts.push(part);
+                }
+            }
+        }
+        if parts.is_empty() {
+            if !["class", "year", "subject", "pattern"].iter().any(|name| fields.contains_key(*name)) {
+                self.report(at, &format!("{} needs a `class`, `year`, `subject` or `pattern`", what));
+            }
+            return None;
+        }
+        Some(parts.join(", "))
+    }
+
+    fn exception(&mut self, except: &Node) -> Option<String> {
+        match *except {
+            Node::Map(ref pairs, _) => {let fields = self.fields(pairs, &["class", "year", "subject", "pattern"], "exception");
+                                       self.rule(&fields, except.pos(), "exception")},
+            _                       => {self.report(except.pos(), "exception should be a mapping with a `class`, `year`, `subject` or `pattern`");
+                                       None},
+        }
+    }
+
+    fn queries(&mut self, queries: &Node) {
+        let pairs = match *queries {
+            Node::Map(ref p, _) => p,
+            _                   => return self.report(queries.pos(), "`queries` should map names to queries"),
+        };
+        for &(ref name, ref raw) in pairs {
+            let name = match name.as_str() {
+                Some(name) => name,
+                None       => {self.report(name.pos(), "query name is not a string");
+                               continue},
+            };
+            match *raw {
+                Node::Scalar(ref q, plain, (line, column)) => if let Err(e) = query::Query::parse(q) {
+                    // Quoted queries start a column after their quote
+                    let column = column + e.column - if plain { 1 } else { 0 };
+                    self.report((line, column), &format!("in query `{}`: {}", name, e.message));
+                },
+                _                                          => self.report(raw.pos(), &format!("query `{}` is not a string", name)),
+            }
+        }
+    }
+
+    fn clashes(&mut self, clashes: &Node) {
+        let pairs = match *clashes {
+            Node::Map(ref p, _) => p,
+            _                   => return self.report(clashes.pos(), "`clashes` should be a mapping"),
+        };
+        let fields = self.fields(pairs, &["per_day", "per_week", "min_lead_days"], "clashes");
+        for &(key, least) in [("per_day", 1), ("per_week", 1), ("min_lead_days", 0)].iter() {
+            if let Some(limit) = fields.get(key) {
+                if !limit.as_int().map_or(false, |n| n >= least) {
+                    self.report(limit.pos(), &format!("`{}` should be a whole number, at least {}", key, least));
+                }
+            }
+        }
+    }
+
+    fn caldav(&mut self, caldav: &Node) {
+        let pairs = match *caldav {
+            Node::Map(ref p, _) => p,
+            _                   => return self.report(caldav.pos(), "`caldav` should be a mapping"),
+        };
+        let fields = self.fields(pairs, &["url", "username", "password"], "caldav");
+        if let Some(url) = self.string(fields.get("url"), caldav.pos(), "caldav `url`") {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                self.report(fields["url"].pos(), "caldav `url` should start with http:// or https://");
+            }
+        }
+        if fields.contains_key("username") {
+            self.string(fields.get("username"), caldav.pos(), "caldav `username`");
+        }
+        // Known, so it isn't reported as a typo, but it is never read from here
+        if let Some(password) = fields.get("password") {
+            self.report(password.pos(), "caldav `password` should not be kept in calendar.yml, set RICHTER_CALDAV_PASSWORD or use the caldav-password file instead");
+        }
+    }
+
+    fn reminders(&mut self, reminders: &Node) {
+        let pairs = match *reminders {
+            Node::Map(ref p, _) => p,
+            _                   => return self.report(reminders.pos(), "`reminders` should be a mapping"),
+        };
+        let fields = self.fields(pairs, &["command", "every", "offsets"], "reminders");
+        self.string(fields.get("command"), reminders.pos(), "reminder `command`");
+
+        if let Some(every) = fields.get("every") {
+            if !every.as_int().map_or(false, |m| m > 0) {
+                self.report(every.pos(), "reminder `every` should be a positive number of minutes");
+            }
+        }
+
+        match fields.get("offsets") {
+            Some(&&Node::Seq(ref offsets, _)) => for offset in offsets {
+                let valid = match offset.as_str() {
+                    Some(o) if offset.as_int().is_some() => reminder::Offset::parse(&format!("{}d", o)).is_ok(),
+                    Some(o)                              => reminder::Offset::parse(o).is_ok(),
+                    None                                 => false,
+                };
+                if !valid {
+                    self.report(offset.pos(), &format!("bad reminder offset `{}`, expected e.g. `2d`, `12h`, `30m` or `evening`",
+                                                       offset.as_str().unwrap_or("...")));
+                }
+            },
+            Some(n)                           => self.report(n.pos(), "reminder `offsets` is not a list"),
+            None                              => {},
+        }
+    }
+}
+
+/// Every problem in the `calendar.yml` at `path`, in the order they appear.
+/// Only fails if the file can't be read.
+pub fn check(path: &PathBuf) -> Result<Vec<Diagnostic>, output::Message> {
+    let mut f = File::open(path)?;
+    let mut raw = String::new();
+    f.read_to_string(&mut raw)?;
+
+    let mut checker = Checker {found: vec![]};
+    let mut builder = Builder::default();
+    if let Err(e) = Parser::new(raw.chars()).load(&mut builder, false) {
+        checker.report(position(e.marker()), &format!("YAML syntax error: {}", e.description()));
+        return Ok(checker.found);
+    }
+    if let Some(msg) = builder.broken {
+        return Err(msg);
+    }
+
+    let pairs = match builder.root {
+        Some(Node::Map(ref pairs, _)) => pairs,
+        Some(ref n)                   => {checker.report(n.pos(), "calendar.yml should be a mapping with `schools` and `enrollments`");
+                                          return Ok(checker.found)},
+        None                          => {checker.report((1, 1), "calendar.yml is empty, it needs `schools` and `enrollments`");
+                                          return Ok(checker.found)},
+    };
+
+    let fields  = checker.fields(pairs, &["schools", "enrollments", "reminders", "queries", "clashes", "caldav"], "calendar.yml");
+    let schools = checker.schools(fields.get("schools"), (1, 1));
+    checker.enrollments(fields.get("enrollments"), (1, 1), &schools);
+    if let Some(reminders) = fields.get("reminders") {
+        checker.reminders(reminders);
+    }
+    if let Some(queries) = fields.get("queries") {
+        checker.queries(queries);
+    }
+    if let Some(clashes) = fields.get("clashes") {
+        checker.clashes(clashes);
+    }
+    if let Some(caldav) = fields.get("caldav") {
+        checker.caldav(caldav);
+    }
+
+    checker.found.sort_by_key(|d| (d.line, d.column));
+    Ok(checker.found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+
+    fn check_yaml(name: &str, yaml: &str) -> Vec<(usize, usize, String)> {
+        let mut path = env::temp_dir();
+        path.push(format!("richter-config-{}-{}.yml", name, ::std::process::id()));
+        File::create(&path).unwrap().write_all(yaml.as_bytes()).unwrap();
+        let found = check(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        found.into_iter().map(|d| (d.line, d.column, d.message)).collect()
+    }
+
+    fn assert_found(found: &[(usize, usize, String)], expected: &[(usize, usize, &str)]) {
+        assert_eq!(found.len(), expected.len(), "{:?}", found);
+        for (&(line, column, ref message), &(want_line, want_column, start)) in found.iter().zip(expected.iter()) {
+            assert_eq!((line, column), (want_line, want_column), "{}", message);
+            assert!(message.starts_with(start), "`{}` doesn't start `{}`", message, start);
+        }
+    }
+
+    #[test]
+    fn reports_each_problem_where_it_is() {
+        let yaml = "schools:\n\
+                    \x20 north:\n\
+                    \x20   id: \"12\"\n\
+                    \x20   subdomain: north\n\
+                    \x20 south:\n\
+                    \x20   id: 3\n\
+                    enrollments:\n\
+                    \x20 - school: north\n\
+                    \x20   clas: 9A\n\
+                    \x20 - school: east\n\
+                    \x20   year: Year 9\n\
+                    \x20 - school: north\n\
+                    \x20   year: Year 9\n\
+                    \x20   except: PE\n\
+                    reminders:\n\
+                    \x20 offsets: [2d, soon]\n\
+                    caldav:\n\
+                    \x20 url: dav.example.com\n\
+                    \x20 password: secret\n";
+        assert_found(&check_yaml("kinds", yaml),
+                     &[( 3,  9, "id for school `north` is quoted"                ),
+                       ( 6,  5, "missing `subdomain` for school `south`"         ),
+                       ( 8,  5, "enrollment needs a `class`"                     ),
+                       ( 9,  5, "unknown key `clas` in enrollment"               ),
+                       (10, 13, "enrollment refers to undefined school `east`"   ),
+                       (14, 13, "exception should be a mapping"                  ),
+                       (16,  3, "missing reminder `command`"                     ),
+                       (16, 17, "bad reminder offset `soon`"                     ),
+                       (18,  8, "caldav `url` should start with http://"         ),
+                       (19, 13, "caldav `password` should not be kept"           ),]);
+    }
+
+    #[test]
+    fn reports_duplicates_at_the_second() {
+        let yaml = "schools:\n\
+                    \x20 north:\n\
+                    \x20   id: 12\n\
+                    \x20   subdomain: north\n\
+                    \x20   subdomain: south\n\
+                    enrollments:\n\
+                    \x20 - school: north\n\
+                    \x20   year: Year 9\n\
+                    \x20 - school: north\n\
+                    \x20   year: Year 9\n";
+        assert_found(&check_yaml("duplicates", yaml),
+                     &[(5, 5, "duplicate key `subdomain` in school `north`"          ),
+                       (9, 5, "duplicate enrollment in `Year 9` at `north`, first at line 7"),]);
+    }
+
+    #[test]
+    fn reports_syntax_errors_alone() {
+        let found = check_yaml("syntax", "schools:\n  north: [12\nenrollments: []\n");
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!(found[0].2.starts_with("YAML syntax error"));
+        assert!(found[0].0 >= 2);
+    }
+}
@@ -1,34 +1,93 @@
+//! Enrollment configuration, read from `calendar.yml`.
+
 extern crate serde_json; 
 extern crate yaml_rust ;
 
-use std::hash::{Hash, Hasher};
 use std::collections::HashMap;
-use std::collections::hash_map::DefaultHasher;
 use std::path::PathBuf;
 use self::yaml_rust::{YamlLoader, YamlEmitter, Yaml};
 use std::fs::File;
 use std::io::Read;
 
 use output;
+use smh;
 
 use calendar;
 
 type SchoolMap = HashMap<String, (i32, String)>;
 
-#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
-pub struct Enrollment {
-    pub subdomain     : String,
-    pub school_id     : i32   ,
-    pub class         : String,
+/// Which entries an enrollment takes. Every field that is set has to match,
+/// so `year: Year 9` with `subject: Maths` is just Year 9 maths.
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    pub class   : Option<String>, // Exact class name, as SMHW has it
+    pub year    : Option<String>, // e.g. `Year 9`
+    pub subject : Option<String>,
+    pub pattern : Option<String>, // Class name with `*` and `?` wildcards, e.g. `9*/Ma`
 }
 
-impl Enrollment {
-    pub fn ident(&self) -> u64 {
-        let mut h = DefaultHasher::new();
-        self.hash(&mut h);
-        h.finish()
+/// Matches a whole name against a pattern where `*` is any run of characters and `?` any one.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name   : Vec<char> = name.chars().collect();
+
+    // Backtracks to the last `*` on a mismatch, letting it swallow one more character
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        }
+        else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        }
+        else if let Some((sp, sn)) = star {
+            p = sp + 1;
+            n = sn + 1;
+            star = Some((sp, sn + 1));
+        }
+        else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl Rule {
+    pub fn is_empty(&self) -> bool {
+        self.class.is_none() && self.year.is_none() && self.subject.is_none() && self.pattern.is_none()
+    }
+
+    pub fn matches(&self, entry: &smh::Entry) -> bool {
+        self.class  .as_ref().map_or(true, |c| *c == entry.class_name  ) &&
+        self.year   .as_ref().map_or(true, |y| *y == entry.year_name   ) &&
+        self.subject.as_ref().map_or(true, |s| *s == entry.subject_name) &&
+        self.pattern.as_ref().map_or(true, |p| glob_match(p, &entry.class_name))
+    }
+
+    /// A short description, e.g. `Year 9, Maths`.
+    pub fn label(&self) -> String {
+        vec![&self.class, &self.year, &self.subject, &self.pattern].into_iter()
+                                                                   .filter_map(|part| part.as_ref())
+                                                                   .cloned()
+                                                                   .collect::<Vec<String>>()
+                                                                   .join(", ")
     }
+}
+
+/// Classes at one school that a calendar follows, as set up in `calendar.yml`:
+/// those matching `rule`, less any matching one of the `except` rules.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Enrollment {
+    pub subdomain     : String   ,
+    pub school_id     : i32      ,
+    pub rule          : Rule     ,
+    pub except        : Vec<Rule>,
+}
 
+impl Enrollment {
     pub fn get_subdomain<'a>(&'a self) -> &'a str {
         &self.subdomain
     }
@@ -36,9 +95,18 @@ impl Enrollment {
     pub fn get_school_id<'a>(&self) -> i32 {
         self.school_id
     }
-    
-    pub fn get_class<'a>(&'a self) -> &'a str {
-        &self.class
+
+    /// Whether this enrollment takes an entry from its school.
+    pub fn matches(&self, entry: &smh::Entry) -> bool {
+        self.rule.matches(entry) && !self.except.iter().any(|rule| rule.matches(entry))
+    }
+
+    /// A short description, e.g. `Year 9 except PE`.
+    pub fn label(&self) -> String {
+        if self.except.is_empty() {
+            return self.rule.label();
+        }
+        format!("{} except {}", self.rule.label(), self.except.iter().map(|r| r.label()).collect::<Vec<String>>().join(" or "))
     }
 
     fn extract_school_name(sch_key: &Yaml) -> Result<String, output::Message> {
@@ -138,34 +206,82 @@ impl Enrollment {
     }
 
 
+    // Picks the rule fields out of an enrollment or one of its exceptions, leaving other keys alone
+    fn extract_rule(rule_val: &Yaml) -> Result<Rule, output::Message> {
+        let mut rule = Rule::default();
+        if let Yaml::Hash(ref rule_info) = *rule_val {
+            for (key, value) in rule_info {
+                if let Yaml::String(ref s) = *key {
+                    let field = match s.as_str() {
+                        "class"   => &mut rule.class  ,
+                        "year"    => &mut rule.year   ,
+                        "subject" => &mut rule.subject,
+                        "pattern" => &mut rule.pattern,
+                        _         => continue         ,
+                    };
+                    match *value {
+                        Yaml::String(ref v) => *field = Some(v.to_owned()),
+                        _                   => return Err(output::Message::new("Reading YAML File",
+                                                                               "Loading Enrollment Info",
+                                                                               &format!("Enrollment {} is not a String", s))),
+                    }
+                }
+            }
+        }
+        else {
+            return Err(output::Message::new("Reading YAML File",
+                                            "Loading Enrollment Info",
+                                            "Enrollment in array is not in form of mapping.",))
+        }
+
+        if rule.is_empty() {
+            return Err(output::Message::new("Reading YAML File",
+                                            "Loading Enrollment Info",
+                                            "Enrollment needs a class, year, subject or pattern, see `richter config check`"));
+        }
+        Ok(rule)
+    }
+
+    // `except` is one rule or a list of them
+    fn extract_exceptions(except_val: &Yaml) -> Result<Vec<Rule>, output::Message> {
+        match *except_val {
+            Yaml::Array(ref rules) => rules.iter().map(Enrollment::extract_rule).collect(),
+            _                      => Ok(vec![Enrollment::extract_rule(except_val)?]),
+        }
+    }
+
     fn extract_enrollment(enrollment_val: &Yaml, schools: &SchoolMap) -> Result<Enrollment, output::Message> {
         if let Yaml::Hash(ref enroll_info) = *enrollment_val {
-            let mut provisional : (Option<i32>, Option<String>, Option<String>) = (None, None, None);
+            let mut provisional : (Option<i32>, Option<String>) = (None, None);
+            let mut except      : Vec<Rule>                     = vec![];
             for (key, value) in enroll_info {
                 if let Yaml::String(ref s) = *key {
                     if s == "school" {
                         if let Yaml::String(ref sch_name) = *value {
-                            if let Some(info) = schools.get(sch_name) {
-                                provisional.1 = Some(info.1.clone());
-                                provisional.0 = Some(info.0);
+                            match schools.get(sch_name) {
+                                Some(info) => {provisional.1 = Some(info.1.clone());
+                                               provisional.0 = Some(info.0);},
+                                None       => return Err(output::Message::new("Reading YAML File",
+                                                                              "Loading Enrollment Info",
+                                                                              &format!("Enrollment refers to undefined school `{}`, see `richter config check`", sch_name))),
                             }
                         }
                     }
-                    else if s == "class" {
-                        if let Yaml::String(ref class_name) = *value {
-                            provisional.2 = Some(class_name.to_owned());
-                        }
+                    else if s == "except" {
+                        except = Enrollment::extract_exceptions(value)?;
                     }
                 }
             }
+            let rule = Enrollment::extract_rule(enrollment_val)?;
 
             match provisional {
-                (Some(school_id), Some(subdomain), Some(class_name)) => return Ok(Enrollment {subdomain  : subdomain ,
-                                                                                              school_id  : school_id ,
-                                                                                              class      : class_name,}),
-                _                                                    => return Err(output::Message::new("Reading YAML File",
-                                                                                                        "Loading Enrollment Info",
-                                                                                                        "Not all required fields found",)),
+                (Some(school_id), Some(subdomain)) => return Ok(Enrollment {subdomain  : subdomain ,
+                                                                            school_id  : school_id ,
+                                                                            rule       : rule      ,
+                                                                            except     : except    ,}),
+                _                                  => return Err(output::Message::new("Reading YAML File",
+                                                                                      "Loading Enrollment Info",
+                                                                                      "Not all required fields found, see `richter config check`",)),
             }
         }
         else {
@@ -192,6 +308,7 @@ impl Enrollment {
         }
     }
 
+    /// Reads every enrollment from a `calendar.yml`.
     pub fn load(path: &PathBuf) -> Result<Vec<Enrollment>, output::Message> {
         let mut f = File::open(path)?;
         let mut enroll_str = String::new();
@@ -217,10 +334,37 @@ impl Enrollment {
     }
 }
 
-impl PartialEq for Enrollment {
-    fn eq(&self, other: &Enrollment) -> bool {
-        (self.ident() == other.ident())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(class: &str, subject: &str) -> smh::Entry {
+        smh::Entry {id: 1, title: String::new(), class_name: class.to_owned(), year_name: "Year 9".to_owned(),
+                    subject_name: subject.to_owned(), employee_id: 0, issued: String::new(), due: String::new()}
+    }
+
+    #[test]
+    fn glob_match_is_whole_name() {
+        assert!( glob_match("9*/Ma", "9A/Ma"));
+        assert!( glob_match("9*/Ma", "9/Ma"));
+        assert!(!glob_match("9*/Ma", "9A/Ma2"));
+        assert!( glob_match("9?/*", "9B/Fr"));
+        assert!(!glob_match("9?/*", "9/Fr"));
+        assert!( glob_match("*a*a*", "banana"));
+        assert!( glob_match("*", ""));
+        assert!(!glob_match("", "x"));
     }
-}
 
-impl Eq for Enrollment {}
+    #[test]
+    fn exceptions_take_entries_back_out() {
+        let pe = Rule {subject: Some("PE".to_owned()), ..Rule::default()};
+        let enrollment = Enrollment {subdomain: "hill".to_owned(), school_id: 1,
+                                     rule  : Rule {year: Some("Year 9".to_owned()), ..Rule::default()},
+                                     except: vec![pe, Rule {pattern: Some("*/Fr?".to_owned()), ..Rule::default()}]};
+        assert!( enrollment.matches(&entry("9A/Ma" , "Maths" )));
+        assert!(!enrollment.matches(&entry("9A/PE" , "PE"    )));
+        assert!(!enrollment.matches(&entry("9A/Fr2", "French")));
+        assert!( enrollment.matches(&entry("9A/Fr" , "French")));
+        assert_eq!(enrollment.label(), "Year 9 except PE or */Fr?");
+    }
+}
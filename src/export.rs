@@ -0,0 +1,474 @@
+//! Exports for people and programs that can't read the JSON cache.
+//!
+//! Entries and the `calendar::SchoolCache` tables go out as a `Sheet`: named
+//! columns of plain strings, which can be narrowed with `select` and written
+//! as CSV or TSV. Entries can also go out as todo.txt lines or Taskwarrior
+//! JSON, each task carrying the entry's subdomain and id so re-importing
+//! updates it rather than adding a copy. Org files are written whole, but
+//! keep the text written under each heading since the last export, and any
+//! heading that isn't one of the exported entries.
+
+extern crate serde_json;
+extern crate time;
+
+use std::collections::BTreeMap;
+
+use smh;
+use enroll;
+use calendar;
+use query;
+use output;
+use digest;
+
+/// Every format `export` accepts by name, `todotxt` aside as another name for `todo`.
+pub const FORMATS : &'static [&'static str] = &["csv", "tsv", "todo", "taskwarrior", "org"];
+
+/// The delimited text formats a `Sheet` can be written as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delimited {
+    Csv,
+    Tsv,
+}
+
+impl Delimited {
+    pub fn from_name(name: &str) -> Option<Delimited> {
+        match name.to_lowercase().as_str() {
+            "csv" => Some(Delimited::Csv),
+            "tsv" => Some(Delimited::Tsv),
+            _     => None                ,
+        }
+    }
+}
+
+/// Rows of strings under named columns.
+#[derive(Debug, Clone)]
+pub struct Sheet {
+    pub columns : Vec<String>     ,
+    pub rows    : Vec<Vec<String>>,
+}
+
+// Quoted as RFC 4180 asks: only when needed, with quotes doubled
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r')
+       || field.starts_with(' ') || field.ends_with(' ') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else {
+        field.to_owned()
+    }
+}
+
+// TSV has no quoting, so the characters that would break a row become spaces
+fn tsv_field(field: &str) -> String {
+    field.chars().map(|c| if c == '\t' || c == '\n' || c == '\r' { ' ' } else { c }).collect()
+}
+
+impl Sheet {
+    fn new(columns: &[&str]) -> Sheet {
+        Sheet {columns: columns.iter().map(|c| (*c).to_owned()).collect(), rows: vec![]}
+    }
+
+    /// Just the `wanted` columns, in the order given.
+    pub fn select(&self, wanted: &[&str]) -> Result<Sheet, output::Message> {
+        let mut indices = vec![];
+        for name in wanted {
+            match self.columns.iter().position(|c| c == name.trim()) {
+                Some(index) => indices.push(index),
+                None        => return Err(output::Message::new("Exporting",
+                                                               "Selecting Columns",
+                                                               &format!("No column `{}`, choose from: {}", name.trim(), self.columns.join(", ")))),
+            }
+        }
+
+        Ok(Sheet {columns: indices.iter().map(|&i| self.columns[i].clone()).collect(),
+                  rows   : self.rows.iter().map(|row| indices.iter().map(|&i| row[i].clone()).collect()).collect()})
+    }
+
+    /// The header and every row, CSV lines ending in CRLF as RFC 4180 has them.
+    pub fn render(&self, format: Delimited) -> String {
+        let (field, separator, end): (fn(&str) -> String, &str, &str) = match format {
+            Delimited::Csv => (csv_field, "," , "\r\n"),
+            Delimited::Tsv => (tsv_field, "\t", "\n"  ),
+        };
+
+        let mut out = String::new();
+        for row in Some(&self.columns).into_iter().chain(self.rows.iter()) {
+            out.push_str(&row.iter().map(|cell| field(cell)).collect::<Vec<String>>().join(separator));
+            out.push_str(end);
+        }
+        out
+    }
+}
+
+/// Entries as a query sees them, with teacher, subject and year names resolved.
+pub fn entries(listed: &[(&enroll::Enrollment, query::Facts)]) -> Sheet {
+    let mut sheet = Sheet::new(&["id", "child", "school", "title", "subject", "class", "year", "teacher",
+                                 "issued", "due", "done", "tags", "notes"]);
+    for &(enrollment, ref facts) in listed {
+        let entry = facts.entry;
+        sheet.rows.push(vec![entry.id.to_string()                             ,
+                             facts.child.clone()                              ,
+                             enrollment.subdomain.clone()                     ,
+                             entry.title.clone()                              ,
+                             entry.subject_name.clone()                       ,
+                             entry.class_name.clone()                         ,
+                             entry.year_name.clone()                          ,
+                             facts.teacher.clone()                            ,
+                             entry.issued.chars().take(10).collect()          ,
+                             entry.due.chars().take(10).collect()             ,
+                             (if facts.done { "yes" } else { "no" }).to_owned(),
+                             facts.tags.join(" ")                             ,
+                             facts.notes.join("; ")                           ,]);
+    }
+    sheet
+}
+
+/// The names of the tables in a `SchoolCache`.
+pub const SCHOOL_TABLES : [&'static str; 4] = ["employees", "subjects", "years", "classes"];
+
+/// One table from each school's cache, rows in id order with the school's subdomain first.
+pub fn school_table(schools: &[&calendar::SchoolCache], table: &str) -> Result<Sheet, output::Message> {
+    let mut sheet = match table {
+        "employees" => Sheet::new(&["school", "id", "title", "forename", "surname", "name"]),
+        "subjects"  => Sheet::new(&["school", "id", "name"]),
+        "years"     => Sheet::new(&["school", "id", "name"]),
+        "classes"   => Sheet::new(&["school", "id", "name", "year"]),
+        _           => return Err(output::Message::new("Exporting",
+                                                       "Choosing Table",
+                                                       &format!("No table `{}`, choose from: entries, {}", table, SCHOOL_TABLES.join(", ")))),
+    };
+
+    for sc in schools {
+        let school = sc.school.subdomain.clone();
+        let mut rows: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+        match table {
+            "employees" => for e in sc.employees.values() {
+                rows.insert(e.id, vec![school.clone(), e.id.to_string(), e.title.clone(), e.forename.clone(), e.surname.clone(), e.display_name()]);
+            },
+            "subjects"  => for s in sc.subjects.values() {
+                rows.insert(s.id, vec![school.clone(), s.id.to_string(), s.name.clone()]);
+            },
+            "years"     => for y in sc.years.values() {
+                rows.insert(y.id, vec![school.clone(), y.id.to_string(), y.name.clone()]);
+            },
+            _           => for c in sc.classes.values() {
+                rows.insert(c.id, vec![school.clone(), c.id.to_string(), c.name.clone(), c.year_name.clone()]);
+            },
+        }
+        sheet.rows.extend(rows.into_iter().map(|(_, row)| row));
+    }
+    Ok(sheet)
+}
+
+// Projects, contexts and tags end at whitespace in both todo.txt and Taskwarrior
+fn word(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join("-")
+}
+
+/// One todo.txt line per entry: creation date from when it was set, the subject
+/// as `+project`, child and class as `@context`s, with `due:` and `smhw:` keys.
+pub fn todo_txt(listed: &[(&enroll::Enrollment, query::Facts)]) -> String {
+    let mut out = String::new();
+    for &(enrollment, ref facts) in listed {
+        let entry = facts.entry;
+        let mut parts = vec![];
+
+        // todo.txt only allows a creation date after a completion date, which isn't kept
+        if facts.done {
+            parts.push("x".to_owned());
+        }
+        else if entry.issued_date().is_some() {
+            parts.push(entry.issued.chars().take(10).collect());
+        }
+        parts.push(entry.title.split_whitespace().collect::<Vec<&str>>().join(" "));
+        parts.push(format!("+{}", word(&entry.subject_name)));
+        parts.push(format!("@{}", word(&facts.child)));
+        if !entry.class_name.is_empty() {
+            parts.push(format!("@{}", word(&entry.class_name)));
+        }
+        if entry.due_date().is_some() {
+            parts.push(format!("due:{}", entry.due.chars().take(10).collect::<String>()));
+        }
+        parts.push(format!("smhw:{}/{}", enrollment.subdomain, entry.id));
+
+        out.push_str(&parts.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+/// A task as `task import` reads it. `smhwid` and `smhwsubdomain` are UDAs,
+/// declared in `.taskrc` with `uda.smhwid.type=numeric` and
+/// `uda.smhwsubdomain.type=string`.
+#[derive(Debug, Serialize)]
+pub struct Task {
+    pub uuid          : String        ,
+    pub description   : String        ,
+    pub status        : String        , // `pending` or `completed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry         : Option<String>, // When it was set, as `20170220T000000Z`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due           : Option<String>,
+    pub project       : String        ,
+    pub tags          : Vec<String>   ,
+    pub smhwid        : i32           ,
+    pub smhwsubdomain : String        ,
+}
+
+// Taskwarrior matches imports by uuid, so an entry has to get the same one every
+// time. Two FNV-1a passes make the 128 bits, marked as a version 8 (custom) UUID.
+fn stable_uuid(subdomain: &str, id: i32) -> String {
+    let name = format!("{}/{}", subdomain, id);
+    let high = (digest::fnv1a(name.as_bytes(), digest::FNV_OFFSET) & !0xf000) | 0x8000;
+    let low  = (digest::fnv1a(name.as_bytes(), 0x84222325cbf29ce4) & 0x3fffffffffffffff) | 0x8000000000000000;
+    format!("{:08x}-{:04x}-{:04x}-{:04x}-{:012x}", high >> 32, (high >> 16) & 0xffff, high & 0xffff, low >> 48, low & 0xffffffffffff)
+}
+
+fn task_date(raw: &str) -> Option<String> {
+    smh::parse_date(raw).map(|_| format!("{}T000000Z", raw.chars().take(10).filter(|c| *c != '-').collect::<String>()))
+}
+
+/// Entries as a JSON array of `Task`s, for `task import`.
+pub fn taskwarrior(listed: &[(&enroll::Enrollment, query::Facts)]) -> Result<String, output::Message> {
+    let tasks: Vec<Task> = listed.iter().map(|&(enrollment, ref facts)| {
+        let entry = facts.entry;
+        let mut tags = vec![word(&facts.child)];
+        if !entry.class_name.is_empty() {
+            tags.push(word(&entry.class_name));
+        }
+        Task {uuid          : stable_uuid(&enrollment.subdomain, entry.id),
+              description   : entry.title.clone(),
+              status        : (if facts.done { "completed" } else { "pending" }).to_owned(),
+              entry         : task_date(&entry.issued),
+              due           : task_date(&entry.due),
+              project       : word(&entry.subject_name),
+              tags          : tags,
+              smhwid        : entry.id,
+              smhwsubdomain : enrollment.subdomain.clone(),}
+    }).collect();
+
+    serde_json::to_string_pretty(&tasks).map_err(|msg| output::Message::new("Exporting",
+                                                                             "Serialising Tasks",
+                                                                             &msg.to_string()))
+}
+
+const ORG_ID     : &'static str = ":SMHW_ID:";
+const ORG_HEADER : &'static str = "#+TITLE: Homework\n#+TODO: TODO | DONE\n";
+
+/// One top level heading of an earlier `org` export, and everything under it.
+#[derive(Debug, Clone, Default)]
+pub struct OrgSection {
+    pub id       : Option<String>, // Its `SMHW_ID` property, if it has one
+    pub planning : String        , // `SCHEDULED:` and `CLOSED:`, without the `DEADLINE:` `org` writes
+    pub body     : String        , // After the planning line and property drawer
+    pub raw      : String        , // The whole section, as it was
+}
+
+/// An earlier `org` export, as far as a new one needs to keep it.
+#[derive(Debug, Clone, Default)]
+pub struct OrgFile {
+    pub preamble : String         , // Before the first heading
+    pub sections : Vec<OrgSection>,
+}
+
+fn is_planning(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("DEADLINE:") || line.starts_with("SCHEDULED:") || line.starts_with("CLOSED:")
+}
+
+// A planning line less its `DEADLINE: <...>`, which `org` writes itself
+fn without_deadline(line: &str) -> String {
+    let kept = match line.find("DEADLINE:") {
+        Some(start) => {
+            let rest = &line[start..];
+            let end  = rest.find('>').map_or(rest.len(), |i| i + 1);
+            format!("{} {}", &line[..start], &rest[end..])
+        },
+        None        => line.to_owned(),
+    };
+    kept.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// Blank lines between headings are written by `org` itself
+fn trim_blank_lines(lines: &[&str]) -> String {
+    let first = lines.iter().position(|l| !l.trim().is_empty()).unwrap_or(lines.len());
+    let last  = lines.iter().rposition(|l| !l.trim().is_empty()).map_or(first, |i| i + 1);
+    lines[first..last].join("\n")
+}
+
+fn org_section(lines: &[&str]) -> OrgSection {
+    let mut section  = OrgSection {raw: trim_blank_lines(lines), .. OrgSection::default()};
+    let mut planning = vec![];
+    let mut drawer   = false;
+    let mut rest     = lines.len();
+
+    // Only the planning lines and drawer straight after the heading are the
+    // entry's, a drawer further down belongs to the body
+    for (n, line) in lines.iter().enumerate().skip(1) {
+        let trimmed = line.trim();
+        if drawer {
+            if trimmed == ":END:" {
+                drawer = false;
+            }
+            else if trimmed.starts_with(ORG_ID) && section.id.is_none() {
+                section.id = Some(trimmed[ORG_ID.len()..].trim().to_owned());
+            }
+        }
+        else if trimmed == ":PROPERTIES:" && section.id.is_none() {
+            drawer = true;
+        }
+        else if is_planning(line) {
+            let kept = without_deadline(trimmed);
+            if !kept.is_empty() {
+                planning.push(kept);
+            }
+        }
+        else if !trimmed.is_empty() {
+            rest = n;
+            break;
+        }
+    }
+
+    section.planning = planning.join(" ");
+    section.body     = trim_blank_lines(&lines[rest..]);
+    section
+}
+
+/// Splits an earlier `org` export into its top level headings. Deeper
+/// headings belong to the one above them.
+pub fn org_sections(previous: &str) -> OrgFile {
+    let lines: Vec<&str> = previous.lines().collect();
+    let mut starts: Vec<usize> = lines.iter()
+                                      .enumerate()
+                                      .filter(|&(_, l)| l.starts_with("* "))
+                                      .map(|(n, _)| n)
+                                      .collect();
+    let preamble = trim_blank_lines(&lines[..starts.first().cloned().unwrap_or(lines.len())]);
+    starts.push(lines.len());
+
+    OrgFile {preamble: preamble,
+             sections: starts.windows(2).map(|w| org_section(&lines[w[0]..w[1]])).collect(),}
+}
+
+fn org_date(raw: &str) -> Option<String> {
+    smh::parse_date(raw).and_then(|day| time::strftime("%Y-%m-%d %a", &day).ok())
+}
+
+/// One heading per entry, TODO or DONE from local completion, with the due date
+/// as its `DEADLINE` and the rest in a property drawer. From `previous`, the
+/// preamble, each entry's other planning and body text, and every section that
+/// isn't one of `listed` are kept as they were.
+pub fn org(listed: &[(&enroll::Enrollment, query::Facts)], previous: &OrgFile) -> String {
+    let mut out = if previous.preamble.is_empty() { ORG_HEADER.to_owned() } else { format!("{}\n", previous.preamble) };
+    let mut ids = vec![];
+
+    for &(enrollment, ref facts) in listed {
+        let entry   = facts.entry;
+        let id      = format!("{}/{}", enrollment.subdomain, entry.id);
+        let section = previous.sections.iter().find(|s| s.id.as_ref() == Some(&id));
+
+        out.push_str(&format!("\n* {} {}\n", if facts.done { "DONE" } else { "TODO" },
+                              entry.title.split_whitespace().collect::<Vec<&str>>().join(" ")));
+
+        let mut planning = vec![];
+        if let Some(due) = org_date(&entry.due) {
+            planning.push(format!("DEADLINE: <{}>", due));
+        }
+        if let Some(s) = section {
+            if !s.planning.is_empty() {
+                planning.push(s.planning.clone());
+            }
+        }
+        if !planning.is_empty() {
+            out.push_str(&format!("  {}\n", planning.join(" ")));
+        }
+
+        out.push_str("  :PROPERTIES:\n");
+        for &(name, ref value) in &[(ORG_ID      , &id               ),
+                                    (":CHILD:"   , &facts.child      ),
+                                    (":TEACHER:" , &facts.teacher    ),
+                                    (":CLASS:"   , &entry.class_name ),
+                                    (":SUBJECT:" , &entry.subject_name)] {
+            if !value.is_empty() {
+                out.push_str(&format!("  {} {}\n", name, value));
+            }
+        }
+        out.push_str("  :END:\n");
+
+        if let Some(s) = section {
+            if !s.body.is_empty() {
+                out.push_str(&s.body);
+                out.push('\n');
+            }
+        }
+        ids.push(id);
+    }
+
+    // Headings of the user's own, and entries filtered out or gone upstream
+    for s in previous.sections.iter().filter(|s| s.id.as_ref().map_or(true, |id| !ids.contains(id))) {
+        out.push('\n');
+        out.push_str(&s.raw);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: i32, title: &str, due: &str) -> smh::Entry {
+        smh::Entry {id: id, title: title.to_owned(), class_name: "9A/Ma".to_owned(), year_name: "Year 9".to_owned(),
+                    subject_name: "Maths".to_owned(), employee_id: 0, issued: String::new(), due: due.to_owned()}
+    }
+
+    fn facts(entry: &smh::Entry) -> query::Facts {
+        query::Facts {entry: entry, teacher: String::new(), done: false, tags: vec![], notes: vec![], child: String::new()}
+    }
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("Maths"), "Maths");
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field(" padded"), "\" padded\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn stable_uuid_never_changes() {
+        let uuid = stable_uuid("hill", 1234);
+        assert_eq!(uuid, stable_uuid("hill", 1234));
+        assert_ne!(uuid, stable_uuid("hill", 1235));
+        assert_ne!(uuid, stable_uuid("dale", 1234));
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "8");
+        assert!("89ab".contains(&uuid[19..20]));
+        // Pinned, as Taskwarrior already holds tasks under the UUIDs made so far
+        assert_eq!(uuid, "06d152cd-7781-896f-a14b-e5fd9b389234");
+    }
+
+    #[test]
+    fn org_keeps_bodies_and_sections_it_did_not_write() {
+        let enrollment = enroll::Enrollment {subdomain: "school".to_owned(), school_id: 1,
+                                             rule: enroll::Rule::default(), except: vec![]};
+        let kept    = entry(1, "Fractions", "2017-02-20T00:00:00.000Z");
+        let dropped = entry(2, "Decimals" , "2017-02-21T00:00:00.000Z");
+
+        let first = org(&[(&enrollment, facts(&kept)), (&enrollment, facts(&dropped))], &OrgFile::default());
+        let edited = first.replace("  :END:\n\n* TODO Decimals", "  :END:\nPages 4 to 6\n** Working\n\n* TODO Decimals")
+                          .replace("  DEADLINE: <2017-02-20 Mon>", "  DEADLINE: <2017-02-20 Mon> SCHEDULED: <2017-02-19 Sun>")
+                          + "\n* My own heading\nShopping list\n";
+
+        let second = org(&[(&enrollment, facts(&kept))], &org_sections(&edited));
+        assert!(second.starts_with(ORG_HEADER));
+        assert!(second.contains("  DEADLINE: <2017-02-20 Mon> SCHEDULED: <2017-02-19 Sun>\n"));
+        assert!(second.contains("  :END:\nPages 4 to 6\n** Working\n"));
+        assert!(second.contains("\n* TODO Decimals\n  DEADLINE: <2017-02-21 Tue>\n"));
+        assert!(second.ends_with("\n* My own heading\nShopping list\n"));
+
+        // Exporting again over its own output changes nothing
+        assert_eq!(org(&[(&enrollment, facts(&kept))], &org_sections(&second)), second);
+    }
+}
@@ -0,0 +1,67 @@
+//! Every entry ever pulled, kept after SMHW drops it, so `stats` can count
+//! a whole term rather than just what the cache holds now.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use smh;
+use output;
+use sidecar;
+
+/// An entry as it was last seen upstream.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Recorded {
+    pub subdomain : String    ,
+    pub teacher   : String    , // `smh::Employee::display_name`, empty if unknown
+    pub entry     : smh::Entry,
+}
+
+/// Pulled entries by `subdomain/id`. Entries are added or updated on every
+/// pull and never removed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    entries : BTreeMap<String, Recorded>, // JSON keys must be strings, so not `smh::EntryKey`
+}
+
+impl History {
+    pub fn load(path: &PathBuf) -> Result<History, output::Message> {
+        sidecar::load(path, "History")
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), output::Message> {
+        sidecar::save(self, path, "History")
+    }
+
+    /// Adds the entry, or replaces what was recorded for it if SMHW has changed it since.
+    pub fn record(&mut self, subdomain: &str, teacher: &str, entry: &smh::Entry) {
+        self.entries.insert(format!("{}/{}", subdomain, entry.id),
+                            Recorded {subdomain: subdomain.to_owned(), teacher: teacher.to_owned(), entry: entry.clone()});
+    }
+
+    pub fn entries(&self) -> Vec<&Recorded> {
+        self.entries.values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: i32, title: &str) -> smh::Entry {
+        smh::Entry {id: id, title: title.to_owned(), class_name: String::new(), year_name: String::new(),
+                    subject_name: String::new(), employee_id: 0, issued: String::new(), due: String::new()}
+    }
+
+    #[test]
+    fn record_keeps_one_per_subdomain_and_id() {
+        let mut history = History::default();
+        history.record("north", "", &entry(1, "Old title"));
+        history.record("north", "", &entry(1, "New title"));
+        history.record("south", "", &entry(1, "Same id, other school"));
+
+        let entries = history.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].entry.title, "New title");
+        assert_eq!(entries[1].subdomain, "south");
+    }
+}
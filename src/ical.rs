@@ -0,0 +1,126 @@
+//! Writing iCalendar (RFC 5545) text, for CalDAV sync and the served feed.
+
+extern crate time;
+
+use smh;
+
+/// One component such as a `VTODO` or `VEVENT`, its properties in order.
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub name       : String               ,
+    pub properties : Vec<(String, String)>, // Name with any parameters, and the value as written
+}
+
+// Lines are at most 75 octets, carrying on after a CRLF and a space
+fn fold(line: &str) -> String {
+    let mut out   = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out
+}
+
+/// Escapes a TEXT value, newlines included.
+pub fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ';'  => out.push_str("\\;") ,
+            ','  => out.push_str("\\,") ,
+            '\n' => out.push_str("\\n") ,
+            '\r' => {}                  ,
+            _    => out.push(c)         ,
+        }
+    }
+    out
+}
+
+/// An SMHW timestamp as a DATE value, e.g. `20170220`.
+pub fn date(raw: &str) -> Option<String> {
+    smh::parse_date(raw).and_then(|day| time::strftime("%Y%m%d", &day).ok())
+}
+
+/// A moment as a UTC DATE-TIME value, e.g. `20170220T093000Z`.
+pub fn stamp(at: &time::Tm) -> String {
+    time::strftime("%Y%m%dT%H%M%SZ", &at.to_utc()).unwrap_or(String::new())
+}
+
+impl Component {
+    pub fn new(name: &str) -> Component {
+        Component {name: name.to_owned(), properties: vec![]}
+    }
+
+    /// Adds a TEXT property, escaped, unless `value` is empty.
+    pub fn text(&mut self, name: &str, value: &str) -> &mut Component {
+        if !value.is_empty() {
+            self.properties.push((name.to_owned(), escape(value)));
+        }
+        self
+    }
+
+    /// Adds a property written as given, for dates and fixed values.
+    pub fn raw(&mut self, name: &str, value: &str) -> &mut Component {
+        self.properties.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// The component's lines, folded and ending in CRLF.
+    pub fn render(&self) -> String {
+        let mut out = format!("BEGIN:{}\r\n", self.name);
+        for &(ref name, ref value) in &self.properties {
+            out.push_str(&fold(&format!("{}:{}", name, value)));
+            out.push_str("\r\n");
+        }
+        out.push_str(&format!("END:{}\r\n", self.name));
+        out
+    }
+}
+
+/// A whole `VCALENDAR` object around `components`, with an optional display name.
+pub fn calendar(name: Option<&str>, components: &[Component]) -> String {
+    let mut out = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//richter//SMHW homework//EN\r\n");
+    if let Some(name) = name {
+        out.push_str(&fold(&format!("X-WR-CALNAME:{}", escape(name))));
+        out.push_str("\r\n");
+    }
+    for component in components {
+        out.push_str(&component.render());
+    }
+    out.push_str("END:VCALENDAR\r\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold_keeps_lines_to_75_octets() {
+        assert_eq!(fold("SUMMARY:short"), "SUMMARY:short");
+
+        let long = format!("SUMMARY:{}", "x".repeat(100));
+        let folded = fold(&long);
+        assert!(folded.split("\r\n").all(|line| line.len() <= 75));
+        assert_eq!(folded.replace("\r\n ", ""), long);
+
+        // Never splits a character
+        let wide = format!("SUMMARY:{}", "é".repeat(60));
+        let folded = fold(&wide);
+        assert!(folded.split("\r\n").all(|line| line.len() <= 75));
+        assert_eq!(folded.replace("\r\n ", ""), wide);
+    }
+
+    #[test]
+    fn escape_covers_text_specials() {
+        assert_eq!(escape("a;b,c\\d"), "a\\;b\\,c\\\\d");
+        assert_eq!(escape("line one\r\nline two"), "line one\\nline two");
+        assert_eq!(escape("plain: text"), "plain: text");
+    }
+}
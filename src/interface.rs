@@ -1,16 +1,24 @@
+//! The HTTP client for the SMHW API.
+
 extern crate rustc_serialize;
 extern crate reqwest;
 extern crate hyper;
+extern crate time;
 
 use std::str::FromStr;
 use std::io::Read;
 use std::collections::HashMap;
+use std::cell::RefCell;
+use std::time::Instant;
+use std::path::PathBuf;
 use smh;
+use auth;
 use output;
 
 use self::rustc_serialize::json;
 use self::hyper::mime::Mime;
 use self::hyper::header::qitem;
+use self::hyper::status::StatusCode;
 
 fn bad_unwrap(activity: &str, message: &str) -> output::Message{
     output::Message::new("Interface", activity, message)
@@ -34,18 +42,26 @@ fn get_string_field(json: &json::Json, field: &str, activity: &str, field_name:
 }
 
 fn get_optional_string_field(json: &json::Json, field: &str, activity: &str, field_name: &str) -> String {
-    match get_string_field(json, "", "", "") {
+    match get_string_field(json, field, activity, field_name) {
         Ok(s)   => s            ,
         Err(_)  => "".to_owned(),
     }
 }
 
+fn get_optional_i32_field(json: &json::Json, field: &str) -> Option<i32> {
+    json.find(field).and_then(|f| f.as_i64()).map(|i| i as i32)
+}
+
 fn get_f32_field(json: &json::Json, field: &str, activity: &str, field_name: &str) -> Result<f32, output::Message> {
     let extracted = json.find(field).ok_or(bad_unwrap(activity, &format!("No {}", field_name)))?
                         .as_f64().ok_or(bad_unwrap(activity, &format!("Bad {}", field_name)))?;
     Ok(extracted as f32)
 }
 
+fn time_now() -> i64 {
+    time::get_time().sec
+}
+
 fn add_query(url: &mut String, params: &HashMap<&str, &str>) {
     url.push_str("?");
     for (key, val) in params {
@@ -59,26 +75,189 @@ fn param_builder<'a>(k: &'a str, v: &'a str) -> HashMap<&'a str, &'a str> {
     params
 }
 
+/// A client for the SMHW API, with one method per endpoint. Every call goes
+/// out to the network, see `calendar::Cache` for a cached view.
 pub struct Interface {
     client    : reqwest::Client,
     user_agent: reqwest::header::UserAgent,
+    session   : Option<RefCell<auth::Session>>,
 }
 
 impl Interface {
-    pub fn new() -> Interface {
-        let client     = reqwest::Client::new().expect("Unable to create HTTP Client.")      ;
+    /// Creates a client for the public SMHW API.
+    pub fn new() -> Result<Interface, output::Message> {
+        let client     = reqwest::Client::new()?                                              ;
         let user_agent = reqwest::header::UserAgent("richter -> (KHTML, like Gecko) Chrome Mozilla AppleWebKit".to_owned());
-        Interface {client: client, user_agent: user_agent}
+        Ok(Interface {client: client, user_agent: user_agent, session: None})
+    }
+
+    /// Creates a client that sends a logged in user's token with every request,
+    /// refreshing it when it expires.
+    pub fn authenticated(session: auth::Session) -> Result<Interface, output::Message> {
+        let mut interface = Interface::new()?;
+        interface.session = Some(RefCell::new(session));
+        Ok(interface)
+    }
+
+    /// Anonymous, or logged in if there is a stored token in the richter directory.
+    pub fn for_dir(richter_dir: &PathBuf) -> Result<Interface, output::Message> {
+        match auth::Session::load(richter_dir)? {
+            Some(session) => Interface::authenticated(session),
+            None          => Interface::new()                 ,
+        }
+    }
+
+    fn token_request(&self, params: &HashMap<&str, &str>) -> Result<json::Json, output::Message> {
+        let endpoint = "https://api.showmyhomework.co.uk/oauth/token";
+        info!("POST {}", endpoint);
+
+        let mut response = self.client.post(endpoint)
+                                      .header(self.user_agent.clone())
+                                      .form(params)
+                                      .send()?;
+        let mut buf = String::new();
+        response.read_to_string(&mut buf)?;
+
+        if !response.status().is_success() {
+            let json = json::Json::from_str(&buf).ok();
+            let why  = json.as_ref()
+                           .and_then(|j| j.find("error_description").or(j.find("error")))
+                           .and_then(|e| e.as_string())
+                           .unwrap_or("Token request refused")
+                           .to_owned();
+            return Err(bad_unwrap("Requesting Token", &format!("{} ({})", why, response.status())));
+        }
+
+        Ok(json::Json::from_str(&buf)?)
+    }
+
+    fn parse_token(json: &json::Json, previous: &auth::Token) -> Result<auth::Token, output::Message> {
+        let expires_in = json.find("expires_in").and_then(|e| e.as_i64()).unwrap_or(0);
+        Ok(auth::Token {
+            access_token  : get_string_field(json, "access_token" , "Requesting Token", "Access Token" )?,
+            refresh_token : get_optional_string_field(json, "refresh_token", "Requesting Token", "Refresh Token"),
+            expires_at    : self::time_now() + expires_in                                                ,
+            subdomain     : previous.subdomain.clone()                                                   ,
+            school_id     : previous.school_id                                                           ,
+            client_id     : previous.client_id.clone()                                                   ,
+            client_secret : previous.client_secret.clone()                                               ,
+        })
+    }
+
+    /// Logs in with a student or parent's username and password.
+    pub fn login(&self, subdomain: &str, school_id: i32, username: &str, password: &str,
+                 client_id: &str, client_secret: &str) -> Result<auth::Token, output::Message> {
+        let id = school_id.to_string();
+        let mut params = HashMap::new();
+        params.insert("grant_type"   , "password"   );
+        params.insert("username"     , username     );
+        params.insert("password"     , password     );
+        params.insert("school_id"    , &id          );
+        params.insert("client_id"    , client_id    );
+        params.insert("client_secret", client_secret);
+
+        let blank = auth::Token {access_token: String::new(), refresh_token: String::new(), expires_at: 0,
+                                 subdomain: subdomain.to_owned(), school_id: school_id,
+                                 client_id: client_id.to_owned(), client_secret: client_secret.to_owned()};
+        Interface::parse_token(&self.token_request(&params)?, &blank)
+    }
+
+    fn refresh(&self, session: &mut auth::Session) -> Result<(), output::Message> {
+        info!("Refreshing login token");
+        let token = session.token.clone();
+        let mut params = HashMap::new();
+        params.insert("grant_type"   , "refresh_token"             );
+        params.insert("refresh_token", &token.refresh_token        );
+        params.insert("client_id"    , &token.client_id            );
+        params.insert("client_secret", &token.client_secret        );
+
+        let fresh = Interface::parse_token(&self.token_request(&params)?, &token)?;
+        session.replace(fresh)
+    }
+
+    // The current access token, refreshed first if it has run out
+    fn bearer(&self, force_refresh: bool) -> Result<Option<String>, output::Message> {
+        match self.session {
+            Some(ref cell) => {
+                let mut session = cell.borrow_mut();
+                if force_refresh || session.token.is_expired() {
+                    self.refresh(&mut session)?;
+                }
+                Ok(Some(session.token.access_token.clone()))
+            },
+            None           => Ok(None),
+        }
     }
 
     fn get_request(&self, endpoint: &str) -> Result<String, output::Message> { 
-        let mut request = self.client.get(endpoint)
-                                     .header(self.user_agent.clone())
-                                     .header(reqwest::header::Accept(vec![qitem(Mime::from_str("application/smhw.v3+json").unwrap())]))
-                                     .send()?;
+        info!("GET {}", endpoint);
+        let started = Instant::now();
+
+        let smhw_json = Mime::from_str("application/smhw.v3+json")
+                             .map_err(|_| bad_unwrap("Building Request", "Bad Accept MIME type"))?;
+
+        // A token can be revoked before it expires, so a 401 earns one refresh and retry
+        let mut request;
+        let mut force_refresh = false;
+        loop {
+            let mut builder = self.client.get(endpoint)
+                                         .header(self.user_agent.clone())
+                                         .header(reqwest::header::Accept(vec![qitem(smhw_json.clone())]));
+            if let Some(token) = self.bearer(force_refresh)? {
+                builder = builder.header(reqwest::header::Authorization(reqwest::header::Bearer {token: token}));
+            }
+            request = builder.send()?;
+            debug!("{} responded {}", endpoint, request.status());
+
+            if *request.status() == StatusCode::Unauthorized && self.session.is_some() && !force_refresh {
+                force_refresh = true;
+                continue;
+            }
+            break;
+        }
+
         let mut buf = String::new();
         request.read_to_string(&mut buf)?;//.expect(&format!("Unable to read request for: {}", endpoint));
 
+        let elapsed = started.elapsed();
+        info!("Received {} bytes in {}ms", buf.len(), elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64);
+
+        Ok(buf)
+    }
+
+    /// Downloads a file, such as an attachment's `file_url`, as raw bytes. Logged in, the
+    /// bearer token goes with it so files on private homework can be fetched.
+    pub fn download(&self, url: &str) -> Result<Vec<u8>, output::Message> {
+        info!("GET {}", url);
+        let started = Instant::now();
+
+        // Private homework's files need the same token as the API, with the same one retry on a 401
+        let mut response;
+        let mut force_refresh = false;
+        loop {
+            let mut builder = self.client.get(url)
+                                         .header(self.user_agent.clone());
+            if let Some(token) = self.bearer(force_refresh)? {
+                builder = builder.header(reqwest::header::Authorization(reqwest::header::Bearer {token: token}));
+            }
+            response = builder.send()?;
+
+            if *response.status() == StatusCode::Unauthorized && self.session.is_some() && !force_refresh {
+                force_refresh = true;
+                continue;
+            }
+            break;
+        }
+        if !response.status().is_success() {
+            return Err(bad_unwrap("Downloading File", &format!("{} responded {}", url, response.status())));
+        }
+
+        let mut buf = vec![];
+        response.read_to_end(&mut buf)?;
+
+        let elapsed = started.elapsed();
+        info!("Received {} bytes in {}ms", buf.len(), elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64);
+
         Ok(buf)
     }
 
@@ -90,6 +269,7 @@ impl Interface {
     }
 
 
+    /// Schools registered under a subdomain.
     pub fn get_schools(&self, subdomain: &str) -> Result<Vec<smh::School>, output::Message> {
         let params = param_builder("subdomain", &subdomain); 
         let endpoint = String::from("https://api.showmyhomework.co.uk/api/schools");
@@ -119,12 +299,13 @@ impl Interface {
             }
         }
         else {
-            output::Message::new("Interface", "Getting Schools", "No School Array in JSON").panic();
+            return Err(output::Message::new("Interface", "Getting Schools", "No School Array in JSON"));
         }
 
         Ok(schools)
     }
 
+    /// Summaries of every calendar entry for a subdomain.
     pub fn get_entries(&self, subdomain: &str) -> Result<Vec<smh::Entry>, output::Message> {
         let params = param_builder("subdomain", subdomain); 
         let endpoint = String::from("https://api.showmyhomework.co.uk/api/calendars");
@@ -149,12 +330,13 @@ impl Interface {
             }
         }
         else {
-            output::Message::new("Interface", "Getting Entries", "No Entry Array in JSON").panic();
+            return Err(output::Message::new("Interface", "Getting Entries", "No Entry Array in JSON"));
         }
 
         Ok(entries)
     }
 
+    /// Teachers and staff of a school.
     pub fn get_employees(&self, school_id: i32) -> Result<Vec<smh::Employee>, output::Message> {
         let id     = school_id.to_string();
         let params = param_builder("school_id", &id); 
@@ -176,12 +358,13 @@ impl Interface {
             }
         }
         else {
-            output::Message::new("Interface", "Getting Employees", "No Employee Array in JSON").panic();
+            return Err(output::Message::new("Interface", "Getting Employees", "No Employee Array in JSON"));
         }
 
         Ok(employees)
     }
 
+    /// Subjects taught at a school.
     pub fn get_subjects(&self, school_id: i32) -> Result<Vec<smh::Subject>, output::Message> {
         let id     = school_id.to_string();
         let params = param_builder("school_id", &id); 
@@ -201,12 +384,13 @@ impl Interface {
             }
         }
         else {
-            output::Message::new("Interface", "Getting Subjects", "No Subject Array in JSON").panic();
+            return Err(output::Message::new("Interface", "Getting Subjects", "No Subject Array in JSON"));
         }
 
         Ok(subjects)
     }
 
+    /// Year groups of a school.
     pub fn get_years(&self, school_id: i32) -> Result<Vec<smh::Year>, output::Message> {
         let id     = school_id.to_string();
         let params = param_builder("school_id", &id); 
@@ -226,12 +410,13 @@ impl Interface {
             }
         }
         else {
-            output::Message::new("Interface", "Getting Years", "No Year Array in JSON").panic();
+            return Err(output::Message::new("Interface", "Getting Years", "No Year Array in JSON"));
         }
 
         Ok(years)
     }
 
+    /// Class groups of a school.
     pub fn get_classes(&self, school_id: i32) -> Result<Vec<smh::Class>, output::Message> {
         let id     = school_id.to_string();
         let params = param_builder("school_id", &id); 
@@ -252,9 +437,64 @@ impl Interface {
             }
         }
         else {
-            output::Message::new("Interface", "Getting Classes", "No Class Array in JSON").panic();
+            return Err(output::Message::new("Interface", "Getting Classes", "No Class Array in JSON"));
         }
 
         Ok(classes) 
     }
+
+    /// An attachment's metadata, the file itself is at its `file_url`.
+    pub fn get_attachment(&self, subdomain: &str, attachment_id: i32) -> Result<smh::Attachment, output::Message> {
+        let params = param_builder("subdomain", subdomain);
+        let endpoint = format!("https://api.showmyhomework.co.uk/api/attachments/{}", attachment_id);
+
+        let json = self.json_request(endpoint, &params)?;
+
+        let att = get_field(&json, "attachment", "Getting Attachment", "Attachment Object")?;
+        Ok(smh::Attachment {
+            id           : get_i32_field   (att, "id"        , "Getting Attachment", "Attachment ID"       )?,
+            file_name    : get_string_field(att, "file_name" , "Getting Attachment", "Attachment File Name")?,
+            file_url     : get_string_field(att, "file_url"  , "Getting Attachment", "Attachment URL"      )?,
+            file_size    : get_optional_i32_field(att, "file_size").unwrap_or(0) as i64                     ,
+        })
+    }
+
+    /// The full homework record behind a calendar entry, with its attachments.
+    pub fn get_homework(&self, subdomain: &str, entry_id: i32) -> Result<smh::Homework, output::Message> {
+        let params = param_builder("subdomain", subdomain);
+        let endpoint = format!("https://api.showmyhomework.co.uk/api/homeworks/{}", entry_id);
+
+        let json = self.json_request(endpoint, &params)?;
+
+        let hw = get_field(&json, "homework", "Getting Homework", "Homework Object")?;
+
+        let mut web_links = vec![];
+        if let Some(link_arr) = hw.find("web_links").and_then(|l| l.as_array()) {
+            for link in link_arr {
+                web_links.push(get_string_field(link, "url", "Getting Homework", "Web Link URL")?);
+            }
+        }
+
+        let mut attachments = vec![];
+        if let Some(id_arr) = hw.find("attachment_ids").and_then(|a| a.as_array()) {
+            for id in id_arr {
+                let id = id.as_i64().ok_or(bad_unwrap("Getting Homework", "Bad Attachment ID"))?;
+                // One broken attachment shouldn't cost the whole homework, or the pull
+                match self.get_attachment(subdomain, id as i32) {
+                    Ok (attachment) => attachments.push(attachment),
+                    Err(msg)        => warn!("Skipping attachment {} of homework {}: {}", id, entry_id, msg),
+                }
+            }
+        }
+
+        Ok(smh::Homework {
+            id                : get_i32_field            (hw, "id"             , "Getting Homework", "Homework ID"         )?,
+            title             : get_string_field         (hw, "title"          , "Getting Homework", "Homework Title"      )?,
+            description       : get_optional_string_field(hw, "description"    , "Getting Homework", "Homework Description") ,
+            submission_method : get_optional_string_field(hw, "submission_type", "Getting Homework", "Submission Method"   ) ,
+            duration          : get_optional_i32_field   (hw, "duration"                                                   ) ,
+            web_links         : web_links                                                                                      ,
+            attachments       : attachments                                                                                    ,
+        })
+    }
 }
@@ -0,0 +1,54 @@
+//! richter keeps a local calendar of homework from Show My Homework (SMHW).
+//!
+//! * `interface` talks to the SMHW API and returns the types in `smh`,
+//!   anonymously or as a user logged in through `auth`.
+//! * `enroll` reads which classes to follow from `calendar.yml`, and
+//!   `config` checks that file for mistakes.
+//! * `calendar` pulls, caches and lists entries for those enrollments,
+//!   one calendar per `profile`, with files kept in `locations`.
+//! * `completion` and `notes` hold local state that survives a pull.
+//! * `query` picks entries by their fields, for `list --where`, and
+//!   `search` finds them by words in their titles and names.
+//! * `stats` counts up homework load by subject, teacher and week over
+//!   every entry in `history`, and `clashes` flags days and weeks with too
+//!   much of it.
+//! * `attachments` downloads homework files next to the cache.
+//! * `export` writes entries and school tables out for other programs.
+//! * `report` builds printable weekly homework sheets in Markdown or HTML.
+//! * `caldav` syncs entries to a CalDAV server as tasks, written by `ical`,
+//!   and `serve` offers them as iCalendar feeds over HTTP.
+//!
+//! Nothing here panics or exits the process, failures come back as
+//! `output::Message`s for the caller to deal with.
+
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate log;
+
+pub mod smh;
+pub mod calendar;
+pub mod interface;
+pub mod enroll;
+pub mod config;
+pub mod output;
+pub mod completion;
+pub mod notes;
+pub mod reminder;
+pub mod attachments;
+pub mod auth;
+pub mod profile;
+pub mod locations;
+pub mod query;
+pub mod search;
+pub mod stats;
+pub mod history;
+pub mod clashes;
+pub mod export;
+pub mod report;
+pub mod ical;
+pub mod caldav;
+pub mod serve;
+
+mod sidecar;
+mod digest;
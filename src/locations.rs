@@ -0,0 +1,178 @@
+//! Where richter keeps its files, following the XDG base directory spec.
+//!
+//! * config: `calendar.yml`, in `$XDG_CONFIG_HOME/richter`
+//! * cache:  `.cache` and `schools.json`, in `$XDG_CACHE_HOME/richter`
+//! * data:   completion, notes, logins, reminders and attachments, in `$XDG_DATA_HOME/richter`
+//!
+//! Older versions kept everything in `~/.richter`, see `migrate`.
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use output;
+
+#[derive(Debug, Clone)]
+pub struct Locations {
+    pub config      : PathBuf        ,
+    pub cache       : PathBuf        ,
+    pub data        : PathBuf        ,
+    pub config_file : Option<PathBuf>, // Replaces the selected profile's calendar.yml
+}
+
+// `$VAR/richter` if VAR is set to an absolute path, as the spec asks, or else `~/<fallback>/richter`
+fn xdg_dir(var: &str, home: &PathBuf, fallback: &str) -> PathBuf {
+    let mut dir = match env::var_os(var).map(PathBuf::from) {
+        Some(ref d) if d.is_absolute() => d.clone(),
+        _                              => {let mut d = (*home).clone();
+                                           d.push(fallback);
+                                           d},
+    };
+    dir.push("richter");
+    dir
+}
+
+impl Locations {
+    /// The XDG locations for the current user.
+    pub fn xdg() -> Result<Locations, output::Message> {
+        let home = match env::home_dir() {
+            Some(home) => home,
+            None       => return Err(output::Message::new("Finding Files",
+                                                          "Looking up Home Directory",
+                                                          "No Home Dir!")),
+        };
+
+        Ok(Locations {config      : xdg_dir("XDG_CONFIG_HOME", &home, ".config"     ),
+                      cache       : xdg_dir("XDG_CACHE_HOME" , &home, ".cache"      ),
+                      data        : xdg_dir("XDG_DATA_HOME"  , &home, ".local/share"),
+                      config_file : None                                              ,})
+    }
+
+    /// Everything in one directory, as `~/.richter` used to be.
+    pub fn single(dir: &PathBuf) -> Locations {
+        Locations {config: (*dir).clone(), cache: (*dir).clone(), data: (*dir).clone(), config_file: None}
+    }
+}
+
+// Moves a file or directory unless there's something there already, returning
+// whether it did. `rename` can't cross filesystems, so fall back to copying.
+fn move_item(from: &PathBuf, to: &PathBuf) -> Result<bool, output::Message> {
+    if !from.exists() || to.exists() {
+        return Ok(false);
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    info!("Moving {} to {}", from.display(), to.display());
+
+    if fs::rename(from, to).is_ok() {
+        return Ok(true);
+    }
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for item in fs::read_dir(from)? {
+            let item = item?;
+            let mut dest = to.clone();
+            dest.push(item.file_name());
+            move_item(&item.path(), &dest)?;
+        }
+        fs::remove_dir(from)?;
+    }
+    else {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(true)
+}
+
+const CONFIG_FILES : &'static [&'static str] = &["calendar.yml"];
+const CACHE_FILES  : &'static [&'static str] = &[".cache", "schools.json"];
+const DATA_FILES   : &'static [&'static str] = &["completed.json", "notes.json", "auth.json", "reminders.json", "attachments"];
+
+// Left in a `~/.richter` that couldn't be emptied, so later runs know it's been dealt with
+const MIGRATED_NOTE : &'static str = "README.txt";
+
+fn migrate_dir(from: &PathBuf, to: &Locations, sub: &[&str]) -> Result<usize, output::Message> {
+    let mut moved = 0;
+    for &(names, root) in [(CONFIG_FILES, &to.config), (CACHE_FILES, &to.cache), (DATA_FILES, &to.data)].iter() {
+        for name in names {
+            let mut src = (*from).clone();
+            src.push(name);
+            let mut dest = (*root).clone();
+            for part in sub {
+                dest.push(part);
+            }
+            dest.push(name);
+            if move_item(&src, &dest)? {
+                moved += 1;
+            }
+        }
+    }
+    Ok(moved)
+}
+
+/// Moves an old `~/.richter` directory, profiles and all, into `to`. Anything
+/// already at the destination is left alone, and so is anything richter
+/// doesn't know about. If that leaves the directory non-empty a README goes in
+/// to say where things went, and later calls do nothing. Returns whether
+/// anything was moved.
+pub fn migrate(legacy: &PathBuf, to: &Locations) -> Result<bool, output::Message> {
+    let mut note = (*legacy).clone();
+    note.push(MIGRATED_NOTE);
+    if !legacy.is_dir() || note.exists() {
+        return Ok(false);
+    }
+
+    let mut moved = migrate_dir(legacy, to, &[])?;
+
+    let mut profiles = (*legacy).clone();
+    profiles.push("profiles");
+    if profiles.is_dir() {
+        for dir in fs::read_dir(&profiles)? {
+            let dir  = dir?;
+            let name = dir.file_name().to_string_lossy().into_owned();
+            moved += migrate_dir(&dir.path(), to, &["profiles", &name])?;
+            let _ = fs::remove_dir(dir.path());
+        }
+        let _ = fs::remove_dir(&profiles);
+    }
+
+    if moved > 0 {
+        warn!("Migrated {} item{} from {} to the XDG base directories", moved, if moved == 1 { "" } else { "s" }, legacy.display());
+    }
+
+    // Only goes if we managed to empty it
+    if fs::remove_dir(legacy).is_err() {
+        warn!("Left {} in place, it still has files richter doesn't know about", legacy.display());
+        File::create(&note)?.write_all(format!("richter no longer uses this directory. Its files were moved to:\n\n  config: {}\n  cache:  {}\n  data:   {}\n\nAnything left here was not recognised and can be deleted.\n",
+                                               to.config.display(), to.cache.display(), to.data.display()).as_bytes())?;
+    }
+    Ok(moved > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_once_and_leaves_a_note() {
+        let mut root = env::temp_dir();
+        root.push(format!("richter-migrate-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        let legacy = root.join("legacy");
+        fs::create_dir_all(&legacy).unwrap();
+        File::create(legacy.join("calendar.yml")).unwrap();
+        File::create(legacy.join("unknown.txt")).unwrap();
+        let to = Locations {config: root.join("config"), cache: root.join("cache"), data: root.join("data"), config_file: None};
+
+        assert!(migrate(&legacy, &to).unwrap());
+        assert!(to.config.join("calendar.yml").exists());
+        assert!(legacy.join(MIGRATED_NOTE).exists());
+        assert!(!migrate(&legacy, &to).unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
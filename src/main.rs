@@ -1,18 +1,38 @@
+extern crate richter;
+extern crate libc;
+extern crate time;
 #[macro_use]
-extern crate serde_derive;
+extern crate log;
 
 use std::env;
+use std::io;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::fs::File;
+use std::process;
+use std::path::PathBuf;
+
+use richter::{smh, calendar, enroll, output, reminder, interface, attachments, auth, profile, locations, config, query, stats, history, clashes, export, report, caldav, serve};
 
 mod command;
-mod smh;
-mod calendar;
-mod interface;
-mod enroll;
-mod output;
+
+// The library hands back Messages, only the CLI decides to give up on one
+fn fail(msg: &output::Message) -> ! {
+    let _ = writeln!(io::stderr(), "Unable to complete operation - an Error occured:");
+    let _ = writeln!(io::stderr(), "{}", msg);
+    process::exit(1)
+}
+
+fn crash(msg: &output::Message) -> ! {
+    let _ = writeln!(io::stderr(), "Unable to complete operation - internal Error:");
+    let _ = writeln!(io::stderr(), "{}", msg);
+    panic!("")
+}
 
 fn get_command() -> command::Command {
-    let verbs = vec!["pull".to_owned()];
-    let parse_result =  command::parse_args(verbs, env::args().collect());
+    let verbs = ["pull", "list", "done", "undone", "note", "tag", "untag", "notes", "watch", "show", "attachments", "login", "logout", "config", "search", "week", "month", "stats", "clashes", "export", "report", "sync", "serve"].iter().map(|v| v.to_string()).collect();
+    let flags = ["all", "all-profiles", "download", "force", "json", "no-color", "q", "quiet", "v", "verbose"].iter().map(|f| f.to_string()).collect();
+    let parse_result =  command::parse_args(verbs, flags, env::args().collect());
 
     match parse_result {
         Ok  (cmd) => return cmd ,
@@ -20,32 +40,794 @@ fn get_command() -> command::Command {
     }
 }
 
-fn get_calendar() -> calendar::Calendar {
-    let mut home = env::home_dir().expect("No Home Dir!");
-    home.push(".richter");
-    let res = calendar::Calendar::load(&home);
+// XDG locations, with `--cache-dir` and `--config` on top, after moving any old ~/.richter over
+fn get_locations(command: &command::Command) -> locations::Locations {
+    let mut locations = locations::Locations::xdg().unwrap_or_else(|msg| fail(&msg));
+    if let Some(dir) = command.get_opt("cache-dir") {
+        locations.cache = PathBuf::from(dir);
+    }
+
+    if let Some(mut legacy) = env::home_dir() {
+        legacy.push(".richter");
+        if let Err(msg) = locations::migrate(&legacy, &locations) {
+            fail(&msg);
+        }
+    }
+
+    if let Some(file) = command.get_opt("config") {
+        locations.config_file = Some(PathBuf::from(file));
+    }
+    locations
+}
+
+fn get_profile(command: &command::Command) -> profile::Profile {
+    match profile::Profile::select(&get_locations(command), command.get_opt("profile")) {
+        Ok (profile) => profile   ,
+        Err(msg)     => fail(&msg),
+    }
+}
+
+fn get_calendar(command: &command::Command) -> calendar::Calendar {
+    let res = calendar::Calendar::load(&get_profile(command));
     match res {
         Ok (cal) => return cal       ,
-        Err(msg) => msg.panic()      ,
+        Err(msg) => crash(&msg)     ,
     }
 }
 
+// Every profile's calendar with `--all-profiles`, otherwise just the selected one
+fn get_calendars(command: &command::Command) -> Vec<calendar::Calendar> {
+    if !command.has_flag("all-profiles") {
+        return vec![get_calendar(command)];
+    }
+
+    let profiles = profile::Profile::all(&get_locations(command)).unwrap_or_else(|msg| fail(&msg));
+    profiles.iter()
+            .map(|p| calendar::Calendar::load(p).unwrap_or_else(|msg| crash(&msg)))
+            .collect()
+}
+
 fn pull_command(command: &command::Command) {
-    let mut home = env::home_dir().expect("No Home Dir!");
-    home.push(".richter");
-    match calendar::Calendar::pull(&home){
-        Err(msg) => msg.panic(),
-        Ok(_)    => {}       ,
+    match calendar::Calendar::pull(&get_profile(command)){
+        Err(msg) => crash(&msg),
+        Ok(cal)  => for clash in cal.clashes(Some(&smh::today())).unwrap_or_else(|msg| fail(&msg)) {
+                        warn!("{}: {}", clash.date, clash.message);
+                    },
+    };
+}
+
+// An entry along with the calendar (and so the child) it came from
+type Listed<'a> = (&'a calendar::Calendar, &'a enroll::Enrollment, &'a smh::Entry);
+
+fn listed<'a>(cals: &'a Vec<calendar::Calendar>) -> Vec<Listed<'a>> {
+    let mut all: Vec<Listed> = vec![];
+    for cal in cals {
+        for (enrollment, entry) in cal.entries() {
+            all.push((cal, enrollment, entry));
+        }
+    }
+    all.sort_by(|a, b| (&a.2.due, a.2.id).cmp(&(&b.2.due, b.2.id)));
+    all
+}
+
+fn entry_table(entries: &Vec<Listed>, labelled: bool) -> output::Table {
+    let mut headers = vec!["ID", "Due", "Subject", "Class", "Tags", "Title"];
+    if labelled {
+        headers.insert(0, "Child");
+    }
+    let flexible = headers.len() - 1;
+    let mut table = output::Table::new(headers, flexible);
+
+    for &(cal, enrollment, entry) in entries {
+        let due: String = entry.due.chars().take(10).collect();
+        let cue = match cal.is_done(enrollment, entry) {
+            true  => output::Cue::Done                            ,
+            false => output::Cue::from_days(entry.days_until_due()),
+        };
+
+        // Notes ride along after the title, so they are the first thing truncated
+        let (tags, title) = match cal.annotation(enrollment, entry) {
+            Some(a) => (format_tags(&a.tags),
+                        if a.notes.is_empty() { entry.title.clone() }
+                        else { format!("{} — {}", entry.title, a.notes.join("; ")) }),
+            None    => (String::new(), entry.title.clone()),
+        };
+
+        let mut row = vec![entry.id.to_string()     ,
+                           due                      ,
+                           entry.subject_name.clone(),
+                           entry.class_name.clone()  ,
+                           tags                     ,
+                           title                    ,];
+        if labelled {
+            row.insert(0, cal.profile().label().to_owned());
+        }
+        table.push(cue, row);
+    }
+    table
+}
+
+fn format_tags<'a, I: IntoIterator<Item = &'a String>>(tags: I) -> String {
+    tags.into_iter().map(|t| format!("#{}", t)).collect::<Vec<String>>().join(" ")
+}
+
+// The `--where` query and any `@saved` ones given as arguments, all of which an entry has to match
+fn get_queries(command: &command::Command) -> Vec<query::Query> {
+    let mut queries = vec![];
+    if let Some(raw) = command.get_opt("where") {
+        queries.push(query::Query::parse(raw).unwrap_or_else(|e| fail(&e.into())));
+    }
+
+    let named: Vec<&str> = command.get_args().into_iter().filter(|a| a.starts_with('@')).collect();
+    if named.is_empty() {
+        return queries;
+    }
+    let saved = query::load_saved(&get_profile(command).calendar_path()).unwrap_or_else(|msg| fail(&msg));
+    for name in named {
+        match saved.get(&name[1..]) {
+            Some(raw) => queries.push(query::Query::parse(raw).unwrap_or_else(|e| fail(&output::Message::new("Reading Query",
+                                                                                                            &format!("Parsing Saved Query {}", name),
+                                                                                                            &e.to_string())))),
+            None      => fail(&output::Message::new("Command Line", "Reading Query", &format!("No saved query {} in calendar.yml", name))),
+        }
+    }
+    queries
+}
+
+fn list_command(command: &command::Command) {
+    let cals    = get_calendars(command);
+    let style   = output::Style::detect(command.has_flag("no-color"));
+    let queries = get_queries(command);
+
+    // Completed entries are hidden unless asked for with `--all`
+    let all     = command.has_flag("all");
+    let tag     = command.get_opt("tag");
+    let entries = listed(&cals)
+                     .into_iter()
+                     .filter(|&(cal, enrollment, entry)| all || !cal.is_done(enrollment, entry))
+                     .filter(|&(cal, enrollment, entry)| match tag {
+                         Some(t) => cal.annotation(enrollment, entry).map_or(false, |a| a.has_tag(t)),
+                         None    => true,
+                     })
+                     .filter(|&(cal, enrollment, entry)| {
+                         let facts = cal.facts(enrollment, entry);
+                         queries.iter().all(|q| q.matches(&facts))
+                     })
+                     .collect();
+    print!("{}", entry_table(&entries, command.has_flag("all-profiles")).render(&style));
+}
+
+// Best match first, done entries included since old homework is often what's being looked for
+fn search_command(command: &command::Command) {
+    let text = command.get_args().join(" ");
+    if text.trim().is_empty() {
+        fail(&output::Message::new("Command Line", "Reading Search", "Usage: richter search <terms>"));
+    }
+    let limit: usize = match command.get_opt("limit") {
+        Some(n) => n.parse().unwrap_or_else(|_| fail(&output::Message::new("Command Line", "Reading Search", "--limit should be a number"))),
+        None    => 20,
+    };
+
+    let cals  = get_calendars(command);
+    let style = output::Style::detect(command.has_flag("no-color"));
+
+    // Each calendar ranks its own results, merging keeps each one's order
+    let mut entries: Vec<Listed> = vec![];
+    let ranked: Vec<Vec<Listed>> = cals.iter()
+                                       .map(|cal| cal.search(&text)
+                                                     .unwrap_or_else(|msg| fail(&msg))
+                                                     .into_iter()
+                                                     .map(|(enrollment, entry)| (cal, enrollment, entry))
+                                                     .collect())
+                                       .collect();
+    for rank in 0..limit {
+        for found in ranked.iter() {
+            if let Some(&listed) = found.get(rank) {
+                entries.push(listed);
+            }
+        }
+    }
+    entries.truncate(limit);
+
+    if entries.is_empty() {
+        println!("Nothing matches `{}`.", text);
+        return;
+    }
+    print!("{}", entry_table(&entries, command.has_flag("all-profiles")).render(&style));
+}
+
+// Subjects shortened to fit a grid cell, initials for several words (`Design Technology` is `DT`)
+fn abbreviate(subject: &str) -> String {
+    let words: Vec<&str> = subject.split_whitespace().collect();
+    match words.len() {
+        0 => String::new()                                                                              ,
+        1 => words[0].chars().take(3).collect()                                                         ,
+        _ => words.iter().filter_map(|w| w.chars().next()).flat_map(|c| c.to_uppercase()).collect(),
+    }
+}
+
+// A week from the Monday on or before `--start` (default today), or with `month`
+// the whole of its month, padded out to whole weeks
+fn get_start(command: &command::Command) -> time::Tm {
+    match command.get_opt("start") {
+        Some(raw) => match smh::parse_day_offset(raw) {
+            Some(days) => smh::days_after(&smh::today(), days),
+            None       => fail(&output::Message::new("Command Line", "Reading Start Date",
+                                                     &format!("`{}` is not a date, try `today`, `+1w`, `-3d` or `2017-03-01`", raw))),
+        },
+        None      => smh::today(),
+    }
+}
+
+fn grid_command(command: &command::Command, month: bool) {
+    let start  = get_start(command);
+    let first  = if month { smh::days_after(&start, 1 - start.tm_mday as i64) } else { start };
+    let monday = smh::week_start(&first);
+    let count  = match month {
+        true  => {let next_month = smh::days_after(&first, 32);
+                  let last       = smh::days_after(&next_month, -(next_month.tm_mday as i64));
+                  ((last - monday).num_days() + 7) / 7 * 7},
+        false => 7,
+    };
+
+    let cals     = get_calendars(command);
+    let style    = output::Style::detect(command.has_flag("no-color"));
+    let queries  = get_queries(command);
+    let all      = command.has_flag("all");
+    let labelled = command.has_flag("all-profiles");
+
+    let mut by_due: HashMap<String, Vec<(output::Cue, String)>> = HashMap::new();
+    for (cal, enrollment, entry) in listed(&cals) {
+        let done  = cal.is_done(enrollment, entry);
+        let facts = cal.facts(enrollment, entry);
+        if (done && !all) || !queries.iter().all(|q| q.matches(&facts)) {
+            continue;
+        }
+
+        let cue  = if done { output::Cue::Done } else { output::Cue::from_days(entry.days_until_due()) };
+        let text = match labelled {
+            true  => format!("{} {} {}", cal.profile().label(), abbreviate(&entry.subject_name), entry.title),
+            false => format!("{} {}", abbreviate(&entry.subject_name), entry.title),
+        };
+        by_due.entry(entry.due.chars().take(10).collect()).or_insert(vec![]).push((cue, text));
+    }
+
+    let today = smh::format_day("%Y-%m-%d", &smh::today());
+    let days  = (0..count).map(|n| {
+        let day = smh::days_after(&monday, n);
+        let key = smh::format_day("%Y-%m-%d", &day);
+        output::Day {label  : smh::format_day(if month { "%d" } else { "%d %b" }, &day),
+                     today  : key == today,
+                     outside: month && day.tm_mon != first.tm_mon,
+                     items  : by_due.remove(&key).unwrap_or(vec![]),}
+    }).collect();
+
+    match month {
+        true  => println!("{}", smh::format_day("%B %Y", &first)),
+        false => println!("Week of {}", smh::format_day("%d %B %Y", &monday)),
+    }
+    print!("{}", output::Grid::new(days).render(&style));
+}
+
+// Counts everything cached, done or not, narrowed by any queries
+fn stats_command(command: &command::Command) {
+    let cals    = get_calendars(command);
+    let queries = get_queries(command);
+    // Counted over history, so homework SMHW has since dropped still shows up
+    let histories: Vec<history::History> = cals.iter().map(|cal| cal.history().unwrap_or_else(|msg| fail(&msg))).collect();
+    let facts: Vec<query::Facts> = cals.iter()
+                                       .zip(histories.iter())
+                                       .flat_map(|(cal, history)| history.entries().into_iter().map(move |recorded| cal.recorded_facts(recorded)))
+                                       .filter(|facts| queries.iter().all(|q| q.matches(facts)))
+                                       .collect();
+    let stats = stats::Stats::gather(&facts);
+
+    if command.has_flag("json") {
+        println!("{}", stats.to_json().unwrap_or_else(|msg| fail(&msg)));
+        return;
+    }
+
+    let style = output::Style::detect(command.has_flag("no-color"));
+    let lead = stats.average_lead_days.map_or(String::new(), |d| format!("{:.1} days", d));
+    print!("{}", output::render_fields(&[("Entries"          , stats.entries.to_string()),
+                                         ("Due from"         , stats.first_due.clone()  ),
+                                         ("Due to"           , stats.last_due.clone()   ),
+                                         ("Average lead time", lead                     ),]));
+
+    for &(heading, counts) in [("Subject"    , &stats.by_subject  ),
+                               ("Teacher"    , &stats.by_teacher  ),
+                               ("Week of"    , &stats.by_week     ),
+                               ("Weekday"    , &stats.by_weekday  ),
+                               ("Busiest day", &stats.busiest_days),].iter() {
+        let mut table = output::Table::new(vec![heading, "Entries"], 0);
+        for count in counts.iter() {
+            table.push(output::Cue::Plain, vec![count.name.clone(), count.count.to_string()]);
+        }
+        print!("\n{}", table.render(&style));
+    }
+}
+
+// Upcoming clashes for each child, or every one with `--all`
+fn clashes_command(command: &command::Command) {
+    let cals     = get_calendars(command);
+    let style    = output::Style::detect(command.has_flag("no-color"));
+    let labelled = command.has_flag("all-profiles");
+    let today    = smh::today();
+    let from     = if command.has_flag("all") { None } else { Some(&today) };
+
+    let mut headers = vec!["Date", "Kind", "IDs", "Problem"];
+    if labelled {
+        headers.insert(0, "Child");
+    }
+    let flexible  = headers.len() - 1;
+    let mut table = output::Table::new(headers, flexible);
+    for cal in cals.iter() {
+        for clash in cal.clashes(from).unwrap_or_else(|msg| fail(&msg)) {
+            let kind = match clash.kind {
+                clashes::Kind::Day         => "day"         ,
+                clashes::Kind::Week        => "week"        ,
+                clashes::Kind::ShortNotice => "short notice",
+            };
+            let days = smh::parse_date(&clash.date).map(|d| (d - today).num_days());
+            let mut row = vec![clash.date.clone(),
+                               kind.to_owned(),
+                               clash.ids.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(" "),
+                               clash.message.clone(),];
+            if labelled {
+                row.insert(0, cal.profile().label().to_owned());
+            }
+            table.push(output::Cue::from_days(days), row);
+        }
+    }
+
+    if table.is_empty() {
+        println!("No clashes.");
+        return;
+    }
+    print!("{}", table.render(&style));
+}
+
+// The schools the calendars' enrollments use, or just the one `--school` names by subdomain or id
+fn export_schools<'a>(command: &command::Command, cals: &'a Vec<calendar::Calendar>) -> Vec<&'a calendar::SchoolCache> {
+    let wanted = command.get_opt("school");
+    let mut seen    = HashSet::new();
+    let mut schools = vec![];
+    for cal in cals {
+        if let Some(cache) = cal.cache() {
+            for enrollment in cal.enrollments() {
+                if let Some(sc) = cache.schools.get(&enrollment.school_id) {
+                    let chosen = wanted.map_or(true, |w| sc.school.subdomain == w || sc.school.id.to_string() == w);
+                    if chosen && seen.insert(enrollment.school_id) {
+                        schools.push(sc);
+                    }
+                }
+            }
+        }
+    }
+
+    if schools.is_empty() {
+        if let Some(w) = wanted {
+            fail(&output::Message::new("Command Line", "Exporting", &format!("No cached school `{}`", w)));
+        }
+    }
+    schools
+}
+
+// Org goes to stdout, or with `--output` over an earlier export keeping its body text
+fn org_export(command: &command::Command, entries: &[(&enroll::Enrollment, query::Facts)]) {
+    let path = match command.get_opt("output") {
+        Some(path) => PathBuf::from(path),
+        None       => {print!("{}", export::org(entries, &export::OrgFile::default())); return},
+    };
+
+    let mut previous = String::new();
+    if path.exists() {
+        if let Err(err) = File::open(&path).and_then(|mut f| f.read_to_string(&mut previous)) {
+            fail(&output::Message::from(err));
+        }
+    }
+    let org = export::org(entries, &export::org_sections(&previous));
+    if let Err(err) = File::create(&path).and_then(|mut f| f.write_all(org.as_bytes())) {
+        fail(&output::Message::from(err));
+    }
+}
+
+// `export [entries|employees|subjects|years|classes]` to stdout, entries by default.
+// `--format` is csv, tsv, todo (todo.txt), taskwarrior or org, the last three for entries only
+fn export_command(command: &command::Command) {
+    let what   = command.get_args().into_iter().find(|a| !a.starts_with('@')).unwrap_or("entries");
+    let format = command.get_opt("format").unwrap_or("csv");
+    let cals   = get_calendars(command);
+
+    let entries = || {
+        let queries = get_queries(command);
+        listed(&cals).into_iter()
+                     .map(|(cal, enrollment, entry)| (enrollment, cal.facts(enrollment, entry)))
+                     .filter(|&(_, ref facts)| queries.iter().all(|q| q.matches(facts)))
+                     .collect::<Vec<(&enroll::Enrollment, query::Facts)>>()
+    };
+
+    // Task formats only make sense for entries, and have no columns to choose
+    match format.to_lowercase().as_str() {
+        "todo" | "todotxt" | "taskwarrior" | "org" if what != "entries" => {
+            fail(&output::Message::new("Command Line", "Exporting", &format!("`{}` can only export entries", format)));
+        },
+        "todo" | "todotxt" => {print!("{}", export::todo_txt(&entries())); return},
+        "taskwarrior"      => {println!("{}", export::taskwarrior(&entries()).unwrap_or_else(|msg| fail(&msg))); return},
+        "org"              => {org_export(command, &entries()); return},
+        _                  => {},
+    }
+
+    let format = export::Delimited::from_name(format)
+                     .unwrap_or_else(|| fail(&output::Message::new("Command Line", "Exporting",
+                                                                   &format!("Unknown format `{}`, expected one of: {}", format, export::FORMATS.join(", ")))));
+    let sheet = match what {
+        "entries" => export::entries(&entries()),
+        table     => export::school_table(&export_schools(command, &cals), table).unwrap_or_else(|msg| fail(&msg)),
+    };
+
+    let sheet = match command.get_opt("columns") {
+        Some(columns) => sheet.select(&columns.split(',').collect::<Vec<&str>>()).unwrap_or_else(|msg| fail(&msg)),
+        None          => sheet,
     };
+    print!("{}", sheet.render(format));
+}
+
+fn report_command(command: &command::Command) {
+    let format  = command.get_opt("format").unwrap_or("md");
+    let monday  = smh::week_start(&get_start(command));
+    let cals    = get_calendars(command);
+    let queries = get_queries(command);
+
+    let sheets = cals.iter().map(|cal| report::Sheet::build(cal, &monday, |enrollment, entry| {
+        let facts = cal.facts(enrollment, entry);
+        queries.iter().all(|q| q.matches(&facts))
+    })).collect();
+    let report = report::Report::new(&monday, sheets);
+
+    match format.to_lowercase().as_str() {
+        "md" | "markdown" => print!("{}", report.to_markdown()),
+        "html"            => print!("{}", report.to_html())    ,
+        _                 => fail(&output::Message::new("Command Line", "Writing Report",
+                                                        &format!("Unknown format `{}`, expected md or html", format))),
+    }
+}
+
+fn get_entry_id(command: &command::Command) -> i32 {
+    let args = command.get_args();
+    match args.first().and_then(|id| id.parse().ok()) {
+        Some(id) => id,
+        None     => fail(&output::Message::new("Command Line", "Reading Entry ID", "Expected a numeric entry id")),
+    }
+}
+
+fn done_command(command: &command::Command, done: bool) {
+    let id = get_entry_id(command);
+    let mut cal = get_calendar(command);
+    if let Err(msg) = cal.set_done(id, done) {
+        fail(&msg);
+    }
+}
+
+// `-v`, `-vv` and `-q` are global, so `richter -v pull` works as well as `richter pull -v`
+fn init_logging(command: &command::Command) {
+    let mut verbosity = (command.count_flag("v") + command.count_flag("verbose")) as i32;
+    if command.has_flag("q") || command.has_flag("quiet") {
+        verbosity = -1;
+    }
+    output::init_logging(verbosity);
+}
+
+fn note_command(command: &command::Command) {
+    let id   = get_entry_id(command);
+    let text = command.get_args()[1..].join(" ");
+    if text.trim() == "" {
+        fail(&output::Message::new("Command Line", "Reading Note", "Expected some text for the note"));
+    }
+
+    let mut cal = get_calendar(command);
+    if let Err(msg) = cal.add_note(id, &text) {
+        fail(&msg);
+    }
+}
+
+fn tag_command(command: &command::Command, add: bool) {
+    let id   = get_entry_id(command);
+    let args = command.get_args();
+    let tags = &args[1..];
+    if tags.is_empty() {
+        fail(&output::Message::new("Command Line", "Reading Tags", "Expected at least one tag"));
+    }
+
+    let mut cal = get_calendar(command);
+    let res = match add {
+        true  => cal.add_tags(id, tags)   ,
+        false => cal.remove_tags(id, tags),
+    };
+    if let Err(msg) = res {
+        fail(&msg);
+    }
+}
+
+fn notes_command(command: &command::Command) {
+    let cal   = get_calendar(command);
+    let style = output::Style::detect(command.has_flag("no-color"));
+
+    // Orphans are annotations whose entry no longer exists upstream
+    let mut table = output::Table::new(vec!["ID", "School", "State", "Tags", "Notes"], 4);
+    for annotation in cal.annotations() {
+        table.push(if annotation.orphaned { output::Cue::Done } else { output::Cue::Plain },
+                   vec![annotation.key.id.to_string()                      ,
+                        annotation.key.subdomain.clone()                   ,
+                        (if annotation.orphaned { "orphan" } else { "" }).to_owned(),
+                        format_tags(&annotation.tags)                      ,
+                        annotation.notes.join("; ")                        ,]);
+    }
+    print!("{}", table.render(&style));
+}
+
+// `serve --bind 127.0.0.1:8080 --every 60`, one profile or all of them
+fn serve_command(command: &command::Command) {
+    let bind  = command.get_opt("bind").unwrap_or("127.0.0.1:8080");
+    let every = match command.get_opt("every").map(|m| m.parse::<u64>()) {
+        Some(Ok(m)) if m > 0 => m ,
+        None                 => 60,
+        _                    => fail(&output::Message::new("Command Line", "Reading Refresh Interval",
+                                                           "`--every` should be a positive number of minutes")),
+    };
+    let profiles = match command.has_flag("all-profiles") {
+        true  => profile::Profile::all(&get_locations(command)).unwrap_or_else(|msg| fail(&msg)),
+        false => vec![get_profile(command)],
+    };
+
+    if let Err(msg) = serve::serve(bind, profiles, every) {
+        fail(&msg);
+    }
+}
+
+fn watch_command(command: &command::Command) {
+    if let Err(msg) = reminder::watch(&get_profile(command)) {
+        fail(&msg);
+    }
+}
+
+fn show_command(command: &command::Command) {
+    let id  = get_entry_id(command);
+    let cal = get_calendar(command);
+
+    let found: Vec<(&enroll::Enrollment, &smh::Entry)> = cal.entries().into_iter().filter(|&(_, e)| e.id == id).collect();
+    if found.is_empty() {
+        fail(&output::Message::new("Command Line", "Showing Entry", &format!("No cached entry with id {}", id)));
+    }
+
+    for (enrollment, entry) in found {
+        let homework = cal.homework(enrollment, entry);
+        let mut fields = vec![("ID"     , entry.id.to_string()                                               ),
+                              ("Title"  , entry.title.clone()                                                ),
+                              ("Subject", entry.subject_name.clone()                                         ),
+                              ("Class"  , entry.class_name.clone()                                           ),
+                              ("Year"   , entry.year_name.clone()                                            ),
+                              ("Teacher", cal.teacher(enrollment, entry).map_or(String::new(), |t| t.display_name())),
+                              ("Issued" , entry.issued.chars().take(10).collect()                            ),
+                              ("Due"    , entry.due.chars().take(10).collect()                               ),
+                              ("Done"   , (if cal.is_done(enrollment, entry) { "yes" } else { "no" }).to_owned()),];
+
+        if let Some(hw) = homework {
+            fields.push(("Submission" , hw.submission_method.clone()                                                   ));
+            fields.push(("Duration"   , hw.duration.map_or(String::new(), |d| format!("{} minutes", d))               ));
+            fields.push(("Links"      , hw.web_links.join(", ")                                                        ));
+            fields.push(("Attachments", hw.attachments.iter().map(|a| a.file_name.clone()).collect::<Vec<String>>().join(", ")));
+        }
+
+        if let Some(a) = cal.annotation(enrollment, entry) {
+            fields.push(("Tags" , format_tags(&a.tags)));
+            fields.push(("Notes", a.notes.join("; ")   ));
+        }
+
+        print!("{}", output::render_fields(&fields));
+
+        match homework {
+            Some(hw) => println!("\n{}\n", hw.plain_description()),
+            None     => println!("\nNo details cached for this entry, try `richter pull`.\n"),
+        }
+    }
+}
+
+fn attachments_command(command: &command::Command) {
+    let id       = get_entry_id(command);
+    let cal      = get_calendar(command);
+    let download = command.has_flag("download");
+    let style    = output::Style::detect(command.has_flag("no-color"));
+
+    let mut store = match attachments::Store::open(&cal.profile().locations().data) {
+        Ok (store) => store    ,
+        Err(msg)   => fail(&msg),
+    };
+    let interface = match download {
+        true  => Some(interface::Interface::for_dir(&cal.profile().data_dir()).unwrap_or_else(|msg| fail(&msg))),
+        false => None                                                                                          ,
+    };
+
+    let mut table = output::Table::new(vec!["ID", "Size", "State", "File"], 3);
+    for (enrollment, entry) in cal.entries().into_iter().filter(|&(_, e)| e.id == id) {
+        let homework = match cal.homework(enrollment, entry) {
+            Some(hw) => hw      ,
+            None     => continue,
+        };
+
+        for attachment in &homework.attachments {
+            let state = match interface {
+                Some(ref i) => match store.save(i, &enrollment.subdomain, entry.id, attachment) {
+                    Ok (attachments::Saved::AlreadyOnDisk) => "on disk"   ,
+                    Ok (attachments::Saved::Linked)        => "linked"    ,
+                    Ok (attachments::Saved::Downloaded)    => "downloaded",
+                    Err(msg)                               => fail(&msg)  ,
+                },
+                None        => match store.is_saved(&enrollment.subdomain, entry.id, attachment) {
+                    true  => "on disk",
+                    false => ""       ,
+                },
+            };
+            let path = store.path_for(&enrollment.subdomain, entry.id, attachment);
+            table.push(output::Cue::Plain,
+                       vec![attachment.id.to_string()       ,
+                            format!("{}K", (attachment.file_size + 1023) / 1024),
+                            state.to_owned()                 ,
+                            match state {
+                                "" => attachment.file_name.clone()         ,
+                                _  => path.to_string_lossy().into_owned()  ,
+                            },]);
+        }
+    }
+
+    if table.is_empty() {
+        println!("No attachments cached for entry {}, try `richter pull`.", id);
+    }
+    else {
+        print!("{}", table.render(&style));
+    }
+}
+
+// Reads a line from the terminal without echoing it back
+fn read_password(prompt: &str) -> String {
+    let _ = write!(io::stderr(), "{}", prompt);
+    let _ = io::stderr().flush();
+
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    let tty = unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut term) == 0 };
+    if tty {
+        let mut quiet = term;
+        quiet.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &quiet) };
+    }
+
+    let mut password = String::new();
+    let read = io::stdin().read_line(&mut password);
+
+    if tty {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) };
+        let _ = writeln!(io::stderr(), "");
+    }
+    if let Err(err) = read {
+        fail(&output::Message::from(err));
+    }
+    password.trim_right_matches(|c| c == '\n' || c == '\r').to_owned()
+}
+
+fn login_command(command: &command::Command) {
+    let args = command.get_args();
+    if args.len() < 2 {
+        fail(&output::Message::new("Command Line", "Logging In", "Usage: richter login <subdomain> <username>"));
+    }
+    let (subdomain, username) = (args[0], args[1]);
+
+    // SMHW issues API clients, these come from the command line or the environment
+    let client_id     = command.get_opt("client-id"    ).map(|c| c.to_owned())
+                               .or(env::var("RICHTER_CLIENT_ID"    ).ok()).unwrap_or(String::new());
+    let client_secret = command.get_opt("client-secret").map(|c| c.to_owned())
+                               .or(env::var("RICHTER_CLIENT_SECRET").ok()).unwrap_or(String::new());
+
+    let interface = interface::Interface::new().unwrap_or_else(|msg| fail(&msg));
+    let school_id = match interface.get_schools(subdomain) {
+        Ok (ref schools) if !schools.is_empty() => schools[0].id,
+        Ok (_)                                  => fail(&output::Message::new("Command Line", "Logging In",
+                                                                              &format!("No school with subdomain {}", subdomain))),
+        Err(msg)                                => fail(&msg),
+    };
+
+    let password = read_password(&format!("Password for {}: ", username));
+    let token    = interface.login(subdomain, school_id, username, &password, &client_id, &client_secret)
+                            .unwrap_or_else(|msg| fail(&msg));
+    if let Err(msg) = auth::Session::create(&get_profile(command).data_dir(), token) {
+        fail(&msg);
+    }
+    println!("Logged in to {} as {}.", subdomain, username);
+}
+
+fn logout_command(command: &command::Command) {
+    match auth::logout(&get_profile(command).data_dir()) {
+        Ok (true)  => println!("Logged out.")    ,
+        Ok (false) => println!("Not logged in."),
+        Err(msg)   => fail(&msg)               ,
+    }
+}
+
+fn config_command(command: &command::Command) {
+    match command.get_args().first() {
+        Some(&"check") => config_check(command),
+        _              => fail(&output::Message::new("Command Line", "Reading Config Command", "Usage: richter config check")),
+    }
+}
+
+// Lists every problem in calendar.yml, exiting non-zero if there were any
+fn config_check(command: &command::Command) {
+    let path  = get_profile(command).calendar_path();
+    let found = config::check(&path).unwrap_or_else(|msg| fail(&msg));
+    for diagnostic in found.iter() {
+        println!("{}:{}", path.display(), diagnostic);
+    }
+
+    if found.is_empty() {
+        println!("{} is fine.", path.display());
+    }
+    else {
+        process::exit(1);
+    }
+}
+
+fn sync_command(command: &command::Command) {
+    match command.get_args().first() {
+        Some(&"caldav") => caldav_sync(command),
+        _               => fail(&output::Message::new("Command Line", "Reading Sync Command", "Usage: richter sync caldav [--force]")),
+    }
+}
+
+// Each profile syncs to the collection in its own calendar.yml
+fn caldav_sync(command: &command::Command) {
+    for cal in get_calendars(command) {
+        let path     = cal.profile().calendar_path();
+        let settings = match caldav::Settings::load(&path, &cal.profile().data_dir()).unwrap_or_else(|msg| fail(&msg)) {
+            Some(settings) => settings,
+            None           => fail(&output::Message::new("Syncing", "Loading CalDAV Settings",
+                                                         &format!("No `caldav` section in {}", path.display()))),
+        };
+
+        let summary = caldav::sync(&cal, settings, command.has_flag("force")).unwrap_or_else(|msg| fail(&msg));
+        println!("{}: {} created, {} updated, {} cancelled, {} unchanged, {} conflicts",
+                 cal.profile().label(), summary.created, summary.updated, summary.cancelled, summary.unchanged, summary.conflicts.len());
+    }
 }
 
 fn main() {
     let command =  get_command();
+    init_logging(&command);
     let optional_verb = command.get_verb();
     if let &Some(ref verb) = optional_verb {
         match verb.trim() {
-            "pull" => pull_command(&command),
-            _      => {}                    ,
+            "pull"        => pull_command(&command)       ,
+            "list"        => list_command(&command)       ,
+            "done"        => done_command(&command, true) ,
+            "undone"      => done_command(&command, false),
+            "note"        => note_command(&command)       ,
+            "tag"         => tag_command(&command, true)  ,
+            "untag"       => tag_command(&command, false) ,
+            "notes"       => notes_command(&command)      ,
+            "watch"       => watch_command(&command)      ,
+            "show"        => show_command(&command)       ,
+            "attachments" => attachments_command(&command),
+            "login"       => login_command(&command)      ,
+            "logout"      => logout_command(&command)     ,
+            "config"      => config_command(&command)     ,
+            "search"      => search_command(&command)     ,
+            "week"        => grid_command(&command, false),
+            "month"       => grid_command(&command, true) ,
+            "stats"       => stats_command(&command)      ,
+            "clashes"     => clashes_command(&command)    ,
+            "export"      => export_command(&command)     ,
+            "report"      => report_command(&command)     ,
+            "sync"        => sync_command(&command)       ,
+            "serve"       => serve_command(&command)      ,
+            _             => {}                           ,
         }
     }
 }
@@ -0,0 +1,90 @@
+//! Notes and tags attached to entries by the user.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+
+use smh;
+use output;
+use sidecar;
+
+/// Free text notes and tags a user has attached to one entry. An annotation
+/// whose entry has gone from SMHW is kept and marked as an orphan.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub key      : smh::EntryKey   ,
+    pub notes    : Vec<String>     ,
+    pub tags     : BTreeSet<String>,
+    pub orphaned : bool            ,
+}
+
+impl Annotation {
+    fn new(key: smh::EntryKey) -> Annotation {
+        Annotation {key: key, notes: vec![], tags: BTreeSet::new(), orphaned: false}
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(&normalise_tag(tag))
+    }
+}
+
+/// Tags are stored without their leading `#`, so `#group-work` and `group-work` match
+pub fn normalise_tag(tag: &str) -> String {
+    tag.trim().trim_left_matches('#').to_lowercase()
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Notes {
+    annotations : Vec<Annotation>,
+}
+
+impl Notes {
+    pub fn load(path: &PathBuf) -> Result<Notes, output::Message> {
+        sidecar::load(path, "Notes")
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), output::Message> {
+        sidecar::save(self, path, "Notes")
+    }
+
+    pub fn get(&self, key: &smh::EntryKey) -> Option<&Annotation> {
+        self.annotations.iter().find(|a| &a.key == key)
+    }
+
+    fn get_or_insert(&mut self, key: smh::EntryKey) -> &mut Annotation {
+        if let Some(index) = self.annotations.iter().position(|a| a.key == key) {
+            return &mut self.annotations[index];
+        }
+        self.annotations.push(Annotation::new(key));
+        self.annotations.last_mut().unwrap()
+    }
+
+    pub fn add_note(&mut self, key: smh::EntryKey, note: &str) {
+        self.get_or_insert(key).notes.push(note.to_owned());
+    }
+
+    pub fn add_tag(&mut self, key: smh::EntryKey, tag: &str) {
+        self.get_or_insert(key).tags.insert(normalise_tag(tag));
+    }
+
+    pub fn remove_tag(&mut self, key: smh::EntryKey, tag: &str) {
+        self.get_or_insert(key).tags.remove(&normalise_tag(tag));
+        self.annotations.retain(|a| !(a.notes.is_empty() && a.tags.is_empty()));
+    }
+
+    pub fn annotations(&self) -> &Vec<Annotation> {
+        &self.annotations
+    }
+
+    /// After a pull, anything not in the fresh set of entries is an orphan.
+    /// Orphans are never deleted, and are adopted again if the entry returns.
+    pub fn mark_orphans(&mut self, present: &HashSet<smh::EntryKey>) {
+        for annotation in self.annotations.iter_mut() {
+            let orphaned = !present.contains(&annotation.key);
+            if orphaned && !annotation.orphaned {
+                info!("Entry {} in {} has gone upstream, keeping its notes as an orphan",
+                      annotation.key.id, annotation.key.subdomain);
+            }
+            annotation.orphaned = orphaned;
+        }
+    }
+}
@@ -1,16 +1,23 @@
+//! Error messages, logging and terminal table rendering.
+
 extern crate reqwest;
 extern crate hyper;
 extern crate rustc_serialize;
 extern crate yaml_rust;
+extern crate libc;
+extern crate log;
 
-use std::process;
+use std::env;
 use std::fmt;
 use std::{io, error};
+use std::io::Write;
 use std::error::Error;
 use std::convert::From;
 
 use self::rustc_serialize::json;
 
+/// The error type for everything in richter: which process failed, what it
+/// was doing at the time, and why.
 #[derive(Debug)]
 pub struct Message {
     process  : String,
@@ -24,7 +31,7 @@ impl error::Error for Message {
     }
 
     fn cause(&self) -> Option<&error::Error> {
-        Some(self)
+        None
     }
 }
 
@@ -42,18 +49,6 @@ impl Message {
                  message  : message.to_owned()  ,
                 }
     }
-
-    pub fn error(&self) -> ! {
-        println!("Unable to complete operation - an Error occured:");
-        println!("{}", self); 
-        process::exit(1)
-    }
-
-    pub fn panic(&self) -> ! {
-        println!("Unable to complete operation - internal Error:");
-        println!("{}", self);
-        panic!("")
-    }
 }
 
 impl From<io::Error> for Message {
@@ -100,4 +95,293 @@ impl From<yaml_rust::ScanError> for Message {
     }
 }
 
+// Log records go to stderr so that listings and JSON on stdout stay clean.
+// Only richter's own records are shown, dependencies are too chatty.
+struct StderrLogger {
+    level : log::LogLevelFilter,
+}
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::LogMetadata) -> bool {
+        metadata.level() <= self.level && metadata.target().starts_with("richter")
+    }
+
+    fn log(&self, record: &log::LogRecord) {
+        if self.enabled(record.metadata()) {
+            let _ = writeln!(io::stderr(), "[{}] {}", record.level(), record.args());
+        }
+    }
+}
+
+/// Sends richter's log records to stderr. Verbosity 0 shows warnings, each
+/// `-v` adds a level and `-q` (negative) drops to errors only.
+pub fn init_logging(verbosity: i32) {
+    let level = match verbosity {
+        v if v < 0 => log::LogLevelFilter::Error,
+        0          => log::LogLevelFilter::Warn ,
+        1          => log::LogLevelFilter::Info ,
+        _          => log::LogLevelFilter::Debug,
+    };
+
+    let _ = log::set_logger(|max_level| {
+        max_level.set(level);
+        Box::new(StderrLogger {level: level})
+    });
+}
+
+/// Colour cues for listing rows, chosen from how close a row is to being due.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cue {
+    Plain       ,
+    Overdue     ,
+    DueToday    ,
+    DueThisWeek ,
+    Done        ,
+}
+
+impl Cue {
+    pub fn from_days(days_until: Option<i64>) -> Cue {
+        match days_until {
+            Some(d) if d <  0 => Cue::Overdue    ,
+            Some(0)           => Cue::DueToday   ,
+            Some(d) if d <  7 => Cue::DueThisWeek,
+            _                 => Cue::Plain      ,
+        }
+    }
+
+    fn ansi(&self) -> Option<&'static str> {
+        match *self {
+            Cue::Plain       => None            ,
+            Cue::Overdue     => Some("\x1b[31m"),
+            Cue::DueToday    => Some("\x1b[33m"),
+            Cue::DueThisWeek => Some("\x1b[32m"),
+            Cue::Done        => Some("\x1b[2m" ),
+        }
+    }
+}
+
+/// How a `Table` should be drawn on the current terminal.
+#[derive(Debug, Clone, Copy)]
+pub struct Style {
+    pub color : bool ,
+    pub width : usize,
+}
+
+impl Style {
+    /// Colour is off if asked for with `--no-color`, if `NO_COLOR` is set, or if stdout isn't a terminal.
+    pub fn detect(no_color: bool) -> Style {
+        let tty   = unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 };
+        let color = !no_color && env::var_os("NO_COLOR").is_none() && tty;
+        Style {color: color, width: terminal_width()}
+    }
+}
+
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { ::std::mem::zeroed() };
+    let res = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if res == 0 && size.ws_col > 0 {
+        return size.ws_col as usize;
+    }
+
+    match env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        Some(cols) => cols,
+        None       => 80  ,
+    }
+}
+
+const COLUMN_GAP : usize = 2;
+const MIN_FLEX   : usize = 8;
+
+fn truncate(cell: &str, width: usize) -> String {
+    if cell.chars().count() <= width {
+        return cell.to_owned();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut cut: String = cell.chars().take(width - 1).collect();
+    cut.push('…');
+    cut
+}
+
+fn pad(cell: &str, width: usize) -> String {
+    let len = cell.chars().count();
+    format!("{}{}", cell, " ".repeat(width.saturating_sub(len)))
+}
+
+/// A listing with aligned columns. One column is flexible: it shrinks to fit the
+/// terminal and has its cells truncated with an ellipsis.
+#[derive(Debug)]
+pub struct Table {
+    headers  : Vec<String>            ,
+    rows     : Vec<(Cue, Vec<String>)>,
+    flexible : usize                  ,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&str>, flexible: usize) -> Table {
+        Table {headers  : headers.iter().map(|h| (*h).to_owned()).collect(),
+               rows     : vec![]  ,
+               flexible : flexible,
+              }
+    }
+
+    pub fn push(&mut self, cue: Cue, row: Vec<String>) {
+        self.rows.push((cue, row));
+    }
 
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn widths(&self, style: &Style) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        for &(_, ref row) in &self.rows {
+            for (index, cell) in row.iter().enumerate() {
+                if index < widths.len() && cell.chars().count() > widths[index] {
+                    widths[index] = cell.chars().count();
+                }
+            }
+        }
+
+        if self.flexible < widths.len() {
+            let fixed: usize = widths.iter()
+                                     .enumerate()
+                                     .filter(|&(index, _)| index != self.flexible)
+                                     .map(|(_, w)| w + COLUMN_GAP)
+                                     .sum();
+            let room = style.width.saturating_sub(fixed);
+            if widths[self.flexible] > room {
+                widths[self.flexible] = if room > MIN_FLEX { room } else { MIN_FLEX };
+            }
+        }
+
+        widths
+    }
+
+    fn render_row(cells: &[String], widths: &[usize], last: usize) -> String {
+        let mut line = String::new();
+        for (index, width) in widths.iter().enumerate() {
+            let cell = truncate(cells.get(index).map(|c| c.as_str()).unwrap_or(""), *width);
+            if index == last {
+                line.push_str(&cell);
+            }
+            else {
+                line.push_str(&pad(&cell, *width + COLUMN_GAP));
+            }
+        }
+        line
+    }
+
+    pub fn render(&self, style: &Style) -> String {
+        let widths = self.widths(style);
+        let last   = widths.len().saturating_sub(1);
+        let mut out = String::new();
+
+        let header = Table::render_row(&self.headers, &widths, last);
+        if style.color {
+            out.push_str(&format!("\x1b[1m{}\x1b[0m\n", header));
+        }
+        else {
+            out.push_str(&format!("{}\n", header));
+        }
+
+        for &(cue, ref row) in &self.rows {
+            let line = Table::render_row(row, &widths, last);
+            match (style.color, cue.ansi()) {
+                (true, Some(code)) => out.push_str(&format!("{}{}\x1b[0m\n", code, line)),
+                _                  => out.push_str(&format!("{}\n", line))               ,
+            }
+        }
+
+        out
+    }
+}
+
+/// One day in a `Grid`.
+#[derive(Debug)]
+pub struct Day {
+    pub label   : String            ,
+    pub today   : bool              ,
+    pub outside : bool              , // Padding from a neighbouring month, drawn dimmed
+    pub items   : Vec<(Cue, String)>,
+}
+
+/// Days laid out a week to a row, Monday first, as on a wall calendar.
+#[derive(Debug)]
+pub struct Grid {
+    days : Vec<Day>,
+}
+
+const MIN_CELL : usize = 6;
+
+impl Grid {
+    /// `days` should start on a Monday, a short last week is padded out.
+    pub fn new(days: Vec<Day>) -> Grid {
+        Grid {days: days}
+    }
+
+    fn paint(text: String, code: Option<&str>, style: &Style) -> String {
+        match (style.color, code) {
+            (true, Some(code)) => format!("{}{}\x1b[0m", code, text),
+            _                  => text                              ,
+        }
+    }
+
+    pub fn render(&self, style: &Style) -> String {
+        let width = ::std::cmp::max(MIN_CELL, style.width.saturating_sub(6) / 7);
+        let cell  = |text: &str| pad(&truncate(text, width), width);
+        let mut out = String::new();
+
+        let names: Vec<String> = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"].iter().map(|n| cell(n)).collect();
+        out.push_str(&format!("{}\n", Grid::paint(names.join("│"), Some("\x1b[1m"), style)));
+
+        let rule = vec!["─".repeat(width); 7].join("┼");
+        for week in self.days.chunks(7) {
+            out.push_str(&format!("{}\n", rule));
+
+            // Today stands out in reverse video, other months' days fade
+            let labels: Vec<String> = week.iter()
+                                          .map(|day| match (day.today, day.outside) {
+                                              (true, _    ) => Grid::paint(cell(&day.label), Some("\x1b[7m"), style),
+                                              (_   , true ) => Grid::paint(cell(&day.label), Some("\x1b[2m"), style),
+                                              _             => cell(&day.label),
+                                          })
+                                          .collect();
+            out.push_str(&format!("{}\n", Grid::fill(labels, width).join("│")));
+
+            let height = week.iter().map(|day| day.items.len()).max().unwrap_or(0);
+            for line in 0..height {
+                let items: Vec<String> = week.iter()
+                                             .map(|day| match day.items.get(line) {
+                                                 Some(&(cue, ref text)) => Grid::paint(cell(text), cue.ansi(), style),
+                                                 None                   => cell(""),
+                                             })
+                                             .collect();
+                out.push_str(&format!("{}\n", Grid::fill(items, width).join("│")));
+            }
+        }
+        out
+    }
+
+    // Blank cells for the days missing from a short last week
+    fn fill(mut cells: Vec<String>, width: usize) -> Vec<String> {
+        while cells.len() < 7 {
+            cells.push(" ".repeat(width));
+        }
+        cells
+    }
+}
+
+/// Renders `label: value` pairs with the values lined up, skipping empty values.
+pub fn render_fields(fields: &[(&str, String)]) -> String {
+    let width = fields.iter().map(|&(label, _)| label.chars().count()).max().unwrap_or(0);
+    let mut out = String::new();
+    for &(label, ref value) in fields {
+        if value.trim() == "" {
+            continue;
+        }
+        out.push_str(&format!("{}{}\n", pad(&format!("{}:", label), width + 1 + COLUMN_GAP), value));
+    }
+    out
+}
@@ -0,0 +1,126 @@
+//! Named profiles, one per child, sharing one set of `locations`.
+//!
+//! The default profile lives at the top of each location, so setups from
+//! before profiles keep working. Named ones live under `profiles/<name>/`,
+//! each with its own `calendar.yml`, cache, completion state and notes.
+//! School data is shared between all of them in `schools.json`.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use locations;
+use output;
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    name      : Option<String>        ,
+    locations : locations::Locations  ,
+}
+
+impl Profile {
+    /// A profile in `locations`, `None` for the default one.
+    pub fn new(locations: &locations::Locations, name: Option<&str>) -> Result<Profile, output::Message> {
+        if let Some(n) = name {
+            if n.is_empty() || n.starts_with('.') || n.contains('/') || n.contains('\\') {
+                return Err(output::Message::new("Selecting Profile",
+                                                "Checking Profile Name",
+                                                &format!("`{}` is not a valid profile name", n)));
+            }
+        }
+        Ok(Profile {name: name.map(|n| n.to_owned()), locations: locations.clone()})
+    }
+
+    /// The profile named on the command line, or else in `RICHTER_PROFILE`, or else the default.
+    pub fn select(locations: &locations::Locations, named: Option<&str>) -> Result<Profile, output::Message> {
+        let from_env = env::var("RICHTER_PROFILE").ok();
+        match named.or(from_env.as_ref().map(|n| n.as_str())) {
+            Some(n) if n != "default" => Profile::new(locations, Some(n)),
+            _                         => Profile::new(locations, None   ),
+        }
+    }
+
+    /// The default profile, if it has been set up, then every named profile.
+    /// A `config_file` override only makes sense for one profile, so it is dropped.
+    pub fn all(locations: &locations::Locations) -> Result<Vec<Profile>, output::Message> {
+        let mut shared = locations.clone();
+        shared.config_file = None;
+
+        let mut profiles = vec![];
+
+        let default = Profile::new(&shared, None)?;
+        if fs::metadata(default.calendar_path()).map(|m| m.len() > 0).unwrap_or(false) {
+            profiles.push(default);
+        }
+
+        let mut named_dir = shared.config.clone();
+        named_dir.push("profiles");
+        if named_dir.is_dir() {
+            let mut names = vec![];
+            for dir in fs::read_dir(&named_dir)? {
+                let dir = dir?;
+                if dir.path().is_dir() {
+                    names.push(dir.file_name().to_string_lossy().into_owned());
+                }
+            }
+            names.sort();
+            for name in names {
+                profiles.push(Profile::new(&shared, Some(&name))?);
+            }
+        }
+
+        Ok(profiles)
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|n| n.as_str())
+    }
+
+    /// What to call this profile in listings.
+    pub fn label(&self) -> &str {
+        self.name().unwrap_or("default")
+    }
+
+    pub fn locations(&self) -> &locations::Locations {
+        &self.locations
+    }
+
+    fn within(&self, root: &PathBuf) -> PathBuf {
+        let mut dir = root.clone();
+        if let Some(ref name) = self.name {
+            dir.push("profiles");
+            dir.push(name);
+        }
+        dir
+    }
+
+    pub fn config_dir(&self) -> PathBuf {
+        self.within(&self.locations.config)
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.within(&self.locations.cache)
+    }
+
+    /// Where this profile's own state (completion, notes, login) lives.
+    pub fn data_dir(&self) -> PathBuf {
+        self.within(&self.locations.data)
+    }
+
+    /// This profile's `calendar.yml`, unless `--config` has pointed elsewhere.
+    pub fn calendar_path(&self) -> PathBuf {
+        match self.locations.config_file {
+            Some(ref file) => file.clone(),
+            None           => {let mut path = self.config_dir();
+                               path.push("calendar.yml");
+                               path},
+        }
+    }
+
+    /// The school cache shared between profiles.
+    pub fn schools_path(&self) -> PathBuf {
+        let mut path = self.locations.cache.clone();
+        path.push("schools.json");
+        path
+    }
+}
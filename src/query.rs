@@ -0,0 +1,454 @@
+//! A small query language for picking entries, as used by `list --where`.
+//!
+//! ```text
+//! subject = "Maths" and due < +3d and teacher ~ "Smith"
+//! not done and (tag = reading or year = "Year 9")
+//! ```
+//!
+//! Names compare without regard to case, `~` means "contains". Dates can
+//! be `today`, `tomorrow`, `yesterday`, `YYYY-MM-DD` or a number of days or
+//! weeks from today such as `+3d`, `-1w`. Named queries live in the
+//! `queries` section of `calendar.yml` and are used as `@name`.
+
+extern crate yaml_rust;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use self::yaml_rust::{YamlLoader, Yaml};
+
+use smh;
+use notes;
+use output;
+
+/// What a query can see of an entry, beyond the entry itself.
+pub struct Facts<'a> {
+    pub entry   : &'a smh::Entry,
+    pub teacher : String        , // As `smh::Employee::display_name`, empty if unknown
+    pub done    : bool          ,
+    pub tags    : Vec<String>   ,
+    pub notes   : Vec<String>   ,
+    pub child   : String        , // The profile's label
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Id, Title, Subject, Class, Year, Teacher, Child, Due, Issued, Done, Tag, Note,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq, Ne, Lt, Le, Gt, Ge, Contains, Excludes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text   (String), // Lowercased, comparisons ignore case
+    Number (i64)   ,
+    Days   (i64)   , // Relative to today
+    Bool   (bool)  ,
+}
+
+/// A parsed query.
+#[derive(Debug, Clone)]
+pub enum Query {
+    And     (Box<Query>, Box<Query>),
+    Or      (Box<Query>, Box<Query>),
+    Not     (Box<Query>)            ,
+    Compare (Field, Op, Value)      ,
+}
+
+/// Why a query didn't parse, and where.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub column  : usize , // From 1, in chars
+    pub message : String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+
+impl From<ParseError> for output::Message {
+    fn from(e: ParseError) -> output::Message {
+        output::Message::new("Reading Query", "Parsing Query", &e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word   (String), // Field names, keywords and unquoted values alike
+    Quoted (String),
+    Op     (Op)    ,
+    Open           ,
+    Close          ,
+}
+
+fn tokenise(raw: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<char> = raw.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c     = chars[i];
+        let start = i + 1;
+        let next  = chars.get(i + 1).cloned();
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let (token, width) = match (c, next) {
+            ('(', _)             => (Token::Open            , 1),
+            (')', _)             => (Token::Close           , 1),
+            ('=', Some('='))     => (Token::Op(Op::Eq)      , 2),
+            ('=', _)             => (Token::Op(Op::Eq)      , 1),
+            ('!', Some('='))     => (Token::Op(Op::Ne)      , 2),
+            ('!', Some('~'))     => (Token::Op(Op::Excludes), 2),
+            ('<', Some('='))     => (Token::Op(Op::Le)      , 2),
+            ('<', _)             => (Token::Op(Op::Lt)      , 1),
+            ('>', Some('='))     => (Token::Op(Op::Ge)      , 2),
+            ('>', _)             => (Token::Op(Op::Gt)      , 1),
+            ('~', _)             => (Token::Op(Op::Contains), 1),
+            ('"', _) | ('\'', _) => {
+                let end = match chars[i + 1..].iter().position(|&q| q == c) {
+                    Some(end) => i + 1 + end,
+                    None      => return Err(ParseError {column: start, message: "Unterminated quote".to_owned()}),
+                };
+                (Token::Quoted(chars[i + 1..end].iter().cloned().collect()), end + 1 - i)
+            },
+            _                    => {
+                let len = chars[i..].iter()
+                                    .take_while(|&&w| !w.is_whitespace() && !"()=!<>~\"'".contains(w))
+                                    .count();
+                if len == 0 {
+                    return Err(ParseError {column: start, message: format!("Unexpected `{}`", c)});
+                }
+                (Token::Word(chars[i..i + len].iter().cloned().collect()), len)
+            },
+        };
+        tokens.push((start, token));
+        i += width;
+    }
+    Ok(tokens)
+}
+
+fn field_named(name: &str) -> Option<Field> {
+    match name.to_lowercase().as_str() {
+        "id"                 => Some(Field::Id     ),
+        "title"              => Some(Field::Title  ),
+        "subject"            => Some(Field::Subject),
+        "class"              => Some(Field::Class  ),
+        "year"               => Some(Field::Year   ),
+        "teacher"            => Some(Field::Teacher),
+        "child" | "profile"  => Some(Field::Child  ),
+        "due"                => Some(Field::Due    ),
+        "issued"             => Some(Field::Issued ),
+        "done"               => Some(Field::Done   ),
+        "tag"   | "tags"     => Some(Field::Tag    ),
+        "note"  | "notes"    => Some(Field::Note   ),
+        _                    => None                ,
+    }
+}
+
+struct Parser {
+    tokens : Vec<(usize, Token)>,
+    at     : usize              ,
+    end    : usize              , // Column just past the query, for errors at the end
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at).map(|&(_, ref t)| t)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.at).map_or(self.end, |&(c, _)| c)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        Err(ParseError {column: self.column(), message: message.to_owned()})
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        let found = match self.peek() {
+            Some(&Token::Word(ref w)) => w.to_lowercase() == word,
+            _                         => false                   ,
+        };
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Query, ParseError> {
+        let mut left = self.and()?;
+        while self.keyword("or") {
+            left = Query::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Query, ParseError> {
+        let mut left = self.not()?;
+        while self.keyword("and") {
+            left = Query::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Query, ParseError> {
+        if self.keyword("not") {
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Query, ParseError> {
+        if self.peek() == Some(&Token::Open) {
+            self.at += 1;
+            let inner = self.or()?;
+            if self.peek() != Some(&Token::Close) {
+                return self.error("Expected `)`");
+            }
+            self.at += 1;
+            return Ok(inner);
+        }
+
+        let field = match self.peek().cloned() {
+            Some(Token::Word(ref name)) => match field_named(name) {
+                Some(field) => field,
+                None        => return self.error(&format!("Unknown field `{}`, expected one of id, title, subject, class, year, \
+                                                           teacher, child, due, issued, done, tag or note", name)),
+            },
+            _                           => return self.error("Expected a field name"),
+        };
+        self.at += 1;
+
+        // `done` on its own reads better than `done = yes`
+        let op = match self.peek() {
+            Some(&Token::Op(op))             => op,
+            _ if field == Field::Done        => return Ok(Query::Compare(Field::Done, Op::Eq, Value::Bool(true))),
+            _                                => return self.error("Expected one of = != < <= > >= ~ !~"),
+        };
+        self.at += 1;
+
+        let (column, raw) = match self.tokens.get(self.at) {
+            Some(&(c, Token::Word  (ref v))) => (c, v.clone()),
+            Some(&(c, Token::Quoted(ref v))) => (c, v.clone()),
+            _                                => return self.error("Expected a value"),
+        };
+        let value = Parser::value(field, op, &raw).map_err(|message| ParseError {column: column, message: message})?;
+        self.at += 1;
+        Ok(Query::Compare(field, op, value))
+    }
+
+    // Checks the value and operator make sense for the field, so mistakes show up before anything is listed
+    fn value(field: Field, op: Op, raw: &str) -> Result<Value, String> {
+        let ordered = op == Op::Lt || op == Op::Le || op == Op::Gt || op == Op::Ge;
+        let textual = op == Op::Contains || op == Op::Excludes;
+        match field {
+            Field::Id                  => {
+                if textual {
+                    return Err("`id` can't be compared with `~`".to_owned());
+                }
+                raw.parse().map(Value::Number).map_err(|_| format!("`{}` is not an id", raw))
+            },
+            Field::Due | Field::Issued => {
+                if textual {
+                    return Err("Dates can't be compared with `~`".to_owned());
+                }
+                smh::parse_day_offset(raw).map(Value::Days)
+                                          .ok_or(format!("`{}` is not a date, try `today`, `+3d`, `-1w` or `2017-03-01`", raw))
+            },
+            Field::Done                => {
+                if op != Op::Eq && op != Op::Ne {
+                    return Err("`done` can only be compared with `=` or `!=`".to_owned());
+                }
+                match raw.to_lowercase().as_str() {
+                    "yes" | "true"  => Ok(Value::Bool(true )),
+                    "no"  | "false" => Ok(Value::Bool(false)),
+                    _               => Err(format!("`{}` is not yes or no", raw)),
+                }
+            },
+            _                          => {
+                if ordered {
+                    return Err("Only dates and ids can be compared with `<` or `>`".to_owned());
+                }
+                match field {
+                    Field::Tag => Ok(Value::Text(notes::normalise_tag(raw))),
+                    _          => Ok(Value::Text(raw.to_lowercase()))        ,
+                }
+            },
+        }
+    }
+}
+
+fn compare<T: PartialOrd>(left: T, op: Op, right: T) -> bool {
+    match op {
+        Op::Eq => left == right,
+        Op::Ne => left != right,
+        Op::Lt => left <  right,
+        Op::Le => left <= right,
+        Op::Gt => left >  right,
+        Op::Ge => left >= right,
+        _      => false        ,
+    }
+}
+
+// Equality and containment on one name, ignoring case
+fn compare_text(text: &str, op: Op, wanted: &str) -> bool {
+    let text = text.to_lowercase();
+    match op {
+        Op::Eq       => text == wanted          ,
+        Op::Ne       => text != wanted          ,
+        Op::Contains =>  text.contains(wanted)  ,
+        Op::Excludes => !text.contains(wanted)  ,
+        _            => false                   ,
+    }
+}
+
+// For tags and notes, `=` and `~` ask whether any of them match and the negations whether none do
+fn compare_any(texts: &[String], op: Op, wanted: &str) -> bool {
+    match op {
+        Op::Ne       => !texts.iter().any(|t| compare_text(t, Op::Eq      , wanted)),
+        Op::Excludes => !texts.iter().any(|t| compare_text(t, Op::Contains, wanted)),
+        _            =>  texts.iter().any(|t| compare_text(t, op          , wanted)),
+    }
+}
+
+impl Query {
+    /// Parses a query, pointing at the column where it went wrong if it doesn't.
+    pub fn parse(raw: &str) -> Result<Query, ParseError> {
+        let mut parser = Parser {tokens: tokenise(raw)?, at: 0, end: raw.chars().count() + 1};
+        if parser.peek().is_none() {
+            return parser.error("Empty query");
+        }
+        let query = parser.or()?;
+        if parser.peek().is_some() {
+            return parser.error("Expected `and`, `or` or the end of the query");
+        }
+        Ok(query)
+    }
+
+    pub fn matches(&self, facts: &Facts) -> bool {
+        match *self {
+            Query::And(ref l, ref r) => l.matches(facts) && r.matches(facts),
+            Query::Or (ref l, ref r) => l.matches(facts) || r.matches(facts),
+            Query::Not(ref q)        => !q.matches(facts),
+            Query::Compare(field, op, ref value) => {
+                let entry = facts.entry;
+                match (field, value) {
+                    (Field::Id     , &Value::Number(n)  ) => compare(entry.id as i64, op, n),
+                    (Field::Due    , &Value::Days(d)    ) => entry.days_until_due().map_or(false, |due| compare(due, op, d)),
+                    (Field::Issued , &Value::Days(d)    ) => entry.issued_date().map_or(false, |issued| compare((issued - smh::today()).num_days(), op, d)),
+                    (Field::Done   , &Value::Bool(b)    ) => compare(facts.done, op, b),
+                    (Field::Tag    , &Value::Text(ref t)) => compare_any(&facts.tags , op, t),
+                    (Field::Note   , &Value::Text(ref t)) => compare_any(&facts.notes, op, t),
+                    (Field::Title  , &Value::Text(ref t)) => compare_text(&entry.title       , op, t),
+                    (Field::Subject, &Value::Text(ref t)) => compare_text(&entry.subject_name, op, t),
+                    (Field::Class  , &Value::Text(ref t)) => compare_text(&entry.class_name  , op, t),
+                    (Field::Year   , &Value::Text(ref t)) => compare_text(&entry.year_name   , op, t),
+                    (Field::Teacher, &Value::Text(ref t)) => compare_text(&facts.teacher     , op, t),
+                    (Field::Child  , &Value::Text(ref t)) => compare_text(&facts.child       , op, t),
+                    _                                     => false,
+                }
+            },
+        }
+    }
+}
+
+/// The named queries in the `queries` section of a `calendar.yml`, unparsed.
+pub fn load_saved(path: &PathBuf) -> Result<HashMap<String, String>, output::Message> {
+    let mut f = File::open(path)?;
+    let mut raw = String::new();
+    f.read_to_string(&mut raw)?;
+
+    let mut saved = HashMap::new();
+    let docs = YamlLoader::load_from_str(&raw)?;
+    if let Some(doc) = docs.first() {
+        match doc["queries"] {
+            Yaml::Hash(ref queries) => for (name, query) in queries {
+                match (name, query) {
+                    (&Yaml::String(ref n), &Yaml::String(ref q)) => {saved.insert(n.to_owned(), q.to_owned());},
+                    _                                            => return Err(output::Message::new("Reading YAML File",
+                                                                                                    "Loading Saved Queries",
+                                                                                                    "Saved queries should map names to query strings")),
+                }
+            },
+            Yaml::BadValue          => {},
+            _                       => return Err(output::Message::new("Reading YAML File",
+                                                                       "Loading Saved Queries",
+                                                                       "`queries` is not a mapping")),
+        }
+    }
+    Ok(saved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(due_in: i64) -> smh::Entry {
+        let due = smh::format_day("%Y-%m-%dT00:00:00.000Z", &smh::days_after(&smh::today(), due_in));
+        smh::Entry {id: 7, title: "Fractions worksheet".to_owned(), class_name: "9A/Ma".to_owned(), year_name: "Year 9".to_owned(),
+                    subject_name: "Maths".to_owned(), employee_id: 0, issued: String::new(), due: due}
+    }
+
+    fn facts(entry: &smh::Entry) -> Facts {
+        Facts {entry: entry, teacher: "Mr J Smith".to_owned(), done: false, tags: vec!["reading".to_owned()],
+               notes: vec![], child: "alice".to_owned()}
+    }
+
+    fn matches(query: &str, facts: &Facts) -> bool {
+        Query::parse(query).unwrap().matches(facts)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let e = entry(2);
+        let f = facts(&e);
+        assert!( matches("subject = maths or subject = art and done", &f));
+        assert!(!matches("(subject = maths or subject = art) and done", &f));
+        assert!( matches("not done and not (tag = chores)", &f));
+    }
+
+    #[test]
+    fn relative_dates_count_from_today() {
+        let e = entry(2);
+        let f = facts(&e);
+        assert!( matches("due < +3d", &f));
+        assert!(!matches("due <= tomorrow", &f));
+        assert!( matches("due > today and due < +1w", &f));
+        assert!(!matches("due < yesterday", &f));
+    }
+
+    #[test]
+    fn tilde_is_contains_ignoring_case() {
+        let e = entry(0);
+        let f = facts(&e);
+        assert!( matches("teacher ~ smith", &f));
+        assert!( matches("title ~ \"FRACTIONS work\"", &f));
+        assert!(!matches("title !~ fraction", &f));
+        assert!( matches("tag != chores and note !~ anything", &f));
+    }
+
+    #[test]
+    fn errors_point_at_the_column() {
+        for &(query, column) in [("subject = maths and", 20),
+                                 ("subjet = maths"     ,  1),
+                                 ("due ~ today"        ,  7),
+                                 ("title = \"open"     ,  9),
+                                 ("(done"              ,  6),
+                                 ("id = seven"         ,  6),].iter() {
+            match Query::parse(query) {
+                Ok (_)   => panic!("`{}` parsed", query),
+                Err(err) => assert_eq!(err.column, column, "`{}`: {}", query, err.message),
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Reminders before due dates, run by `watch`.
+
+extern crate yaml_rust;
+extern crate time;
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+use std::fs::{File, OpenOptions};
+use std::io::Read;
+use std::process;
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use self::yaml_rust::{YamlLoader, Yaml};
+
+use smh;
+use enroll;
+use calendar;
+use output;
+use sidecar;
+use profile;
+
+/// When to remind, relative to the start of the day an entry is due
+#[derive(Debug, Clone)]
+pub enum Offset {
+    Before  (time::Duration, String),
+    Evening (i64           , String), // 18:00 on the day n days before
+}
+
+impl Offset {
+    /// Accepts `2d`, `12h`, `30m`, `evening` or `2 evenings`
+    pub fn parse(raw: &str) -> Result<Offset, output::Message> {
+        let label = raw.trim().to_owned();
+        let bad   = || output::Message::new("Reading YAML File",
+                                            "Loading Reminder Offsets",
+                                            &format!("Bad reminder offset `{}`", raw));
+
+        let words: Vec<&str> = label.split_whitespace().collect();
+        if words.last().map_or(false, |w| w.starts_with("evening")) {
+            let count = match words.len() {
+                1 => 1                                    ,
+                2 => words[0].parse().map_err(|_| bad())?,
+                _ => return Err(bad())                    ,
+            };
+            return Ok(Offset::Evening(count, label.clone()));
+        }
+
+        // The unit is the last char, which needn't be one byte long
+        let (at, unit) = match label.char_indices().last() {
+            Some((at, unit)) if at > 0 => (at, unit),
+            _                          => return Err(bad()),
+        };
+        let count: i64 = label[..at].trim().parse().map_err(|_| bad())?;
+        match unit {
+            'd' => Ok(Offset::Before(time::Duration::days   (count), label.clone())),
+            'h' => Ok(Offset::Before(time::Duration::hours  (count), label.clone())),
+            'm' => Ok(Offset::Before(time::Duration::minutes(count), label.clone())),
+            _   => Err(bad()),
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match *self {
+            Offset::Before (_, ref l) => l,
+            Offset::Evening(_, ref l) => l,
+        }
+    }
+
+    fn fires_at(&self, due: time::Timespec) -> time::Timespec {
+        match *self {
+            Offset::Before (d, _) => due - d,
+            Offset::Evening(n, _) => due - time::Duration::days(n) + time::Duration::hours(18),
+        }
+    }
+}
+
+/// The `reminders` section of calendar.yml
+#[derive(Debug)]
+pub struct Config {
+    pub command : String     ,
+    pub every   : u64        , // Minutes between pulls
+    pub offsets : Vec<Offset>,
+}
+
+impl Config {
+    /// Reads the `reminders` section, `every` defaults to 60 and `offsets` to a day.
+    pub fn load(path: &PathBuf) -> Result<Config, output::Message> {
+        let mut f = File::open(path)?;
+        let mut raw = String::new();
+        f.read_to_string(&mut raw)?;
+
+        let mut docs = YamlLoader::load_from_str(&raw)?;
+        let doc = match docs.pop() {
+            Some(doc) => doc,
+            None      => return Err(output::Message::new("Reading YAML File",
+                                                         "Loading from String",
+                                                         "No YAML docs in read")),
+        };
+
+        let ref reminders = doc["reminders"];
+        if reminders.is_badvalue() {
+            return Err(output::Message::new("Reading YAML File",
+                                            "Loading Reminders",
+                                            "No reminders section in calendar.yml"));
+        }
+
+        let command = match reminders["command"] {
+            Yaml::String(ref c) => c.to_owned(),
+            _                   => return Err(output::Message::new("Reading YAML File",
+                                                                   "Loading Reminders",
+                                                                   "Reminder command is not a String")),
+        };
+
+        let every = match reminders["every"] {
+            Yaml::Integer(m) if m > 0 => m as u64,
+            Yaml::BadValue            => 60      ,
+            _                         => return Err(output::Message::new("Reading YAML File",
+                                                                         "Loading Reminders",
+                                                                         "Reminder interval is not a positive Integer")),
+        };
+
+        let mut offsets = vec![];
+        match reminders["offsets"] {
+            Yaml::Array(ref raw_offsets) => for raw_offset in raw_offsets {
+                match *raw_offset {
+                    Yaml::String (ref o) => offsets.push(Offset::parse(o)?),
+                    Yaml::Integer(d)     => offsets.push(Offset::parse(&format!("{}d", d))?),
+                    _                    => return Err(output::Message::new("Reading YAML File",
+                                                                            "Loading Reminder Offsets",
+                                                                            "Offset is not a String")),
+                }
+            },
+            Yaml::BadValue => offsets.push(Offset::parse("1d")?),
+            _              => return Err(output::Message::new("Reading YAML File",
+                                                              "Loading Reminder Offsets",
+                                                              "Array of offsets not found.")),
+        }
+
+        Ok(Config {command: command, every: every, offsets: offsets})
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Fired {
+    key    : smh::EntryKey,
+    offset : String       ,
+}
+
+// Reminders already sent, kept on disk so a restarted watch doesn't repeat them
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct History {
+    fired : BTreeSet<Fired>,
+}
+
+// Midnight, local time, at the start of the day the entry is due
+fn due_moment(entry: &smh::Entry) -> Option<time::Timespec> {
+    match entry.due_date() {
+        Some(due) => {
+            let mut local  = time::now();
+            local.tm_year  = due.tm_year;
+            local.tm_mon   = due.tm_mon ;
+            local.tm_mday  = due.tm_mday;
+            local.tm_hour  = 0;
+            local.tm_min   = 0;
+            local.tm_sec   = 0;
+            local.tm_nsec  = 0;
+            Some(local.to_timespec())
+        },
+        None      => None,
+    }
+}
+
+fn fire(config: &Config, enrollment: &enroll::Enrollment, entry: &smh::Entry, offset: &Offset) {
+    info!("Reminding about entry {} ({} before due)", entry.id, offset.label());
+    let status = process::Command::new("sh")
+                                  .arg("-c")
+                                  .arg(&config.command)
+                                  .env("RICHTER_ENTRY_ID"  , entry.id.to_string())
+                                  .env("RICHTER_SUBDOMAIN" , &enrollment.subdomain)
+                                  .env("RICHTER_CLASS"     , &entry.class_name)
+                                  .env("RICHTER_SUBJECT"   , &entry.subject_name)
+                                  .env("RICHTER_YEAR"      , &entry.year_name)
+                                  .env("RICHTER_TITLE"     , &entry.title)
+                                  .env("RICHTER_ISSUED"    , &entry.issued)
+                                  .env("RICHTER_DUE"       , &entry.due)
+                                  .env("RICHTER_OFFSET"    , offset.label())
+                                  .status();
+    match status {
+        Ok (s) if !s.success() => warn!("Reminder command exited with {}", s),
+        Err(e)                 => warn!("Unable to run reminder command: {}", e),
+        _                      => {}
+    }
+}
+
+fn check(config: &Config, cal: &calendar::Calendar, history: &mut History) -> bool {
+    let now = time::get_time();
+    let mut fired_any = false;
+
+    for (enrollment, entry) in cal.entries() {
+        if cal.is_done(enrollment, entry) {
+            continue;
+        }
+        let due = match due_moment(entry) {
+            Some(due) => due     ,
+            None      => continue,
+        };
+
+        for offset in &config.offsets {
+            let key = Fired {key: smh::EntryKey::new(&enrollment.subdomain, entry.id), offset: offset.label().to_owned()};
+            if now >= offset.fires_at(due) && now < due && !history.fired.contains(&key) {
+                fire(config, enrollment, entry, offset);
+                history.fired.insert(key);
+                fired_any = true;
+            }
+        }
+    }
+
+    fired_any
+}
+
+// Forgets reminders for entries that are past due or gone, they can't fire again
+fn prune(cal: &calendar::Calendar, history: &mut History) -> bool {
+    let now = time::get_time();
+    let pending: HashSet<smh::EntryKey> = cal.entries().into_iter()
+                                             .filter(|&(_, entry)| due_moment(entry).map_or(false, |due| now < due))
+                                             .map(|(enrollment, entry)| smh::EntryKey::new(&enrollment.subdomain, entry.id))
+                                             .collect();
+    let before = history.fired.len();
+    history.fired = history.fired.iter().filter(|f| pending.contains(&f.key)).cloned().collect();
+    history.fired.len() != before
+}
+
+/// Pulls every `every` minutes and checks for due reminders once a minute.
+/// A failed pull is logged and the last good calendar is kept, the cached one
+/// if it was the first; a `calendar.yml` that stops parsing keeps the last good
+/// settings the same way. Reminders for past due entries are forgotten.
+pub fn watch(profile: &profile::Profile) -> Result<(), output::Message> {
+    let mut history_path = profile.data_dir();
+    history_path.push("reminders");
+    history_path.set_extension("json");
+    OpenOptions::new().create(true).read(true).write(true).open(&history_path)?;
+
+    let mut history: History = sidecar::load(&history_path, "Reminders")?;
+    let mut cal    = match calendar::Calendar::pull(profile) {
+        Ok (fresh) => fresh,
+        Err(msg)   => {warn!("Pull failed, starting from the cached calendar: {}", msg);
+                       calendar::Calendar::load(profile)?},
+    };
+    let mut pulled = time::get_time();
+    let mut config = Config::load(&profile.calendar_path())?;
+
+    loop {
+        if time::get_time() - pulled >= time::Duration::minutes(config.every as i64) {
+            match calendar::Calendar::pull(profile) {
+                Ok (fresh) => cal = fresh,
+                Err(msg)   => warn!("Pull failed, keeping previous calendar: {}", msg),
+            }
+            pulled = time::get_time();
+        }
+
+        if check(&config, &cal, &mut history) | prune(&cal, &mut history) {
+            sidecar::save(&history, &history_path, "Reminders")?;
+        }
+
+        thread::sleep(StdDuration::from_secs(60));
+
+        match Config::load(&profile.calendar_path()) {
+            Ok (fresh) => config = fresh,
+            Err(msg)   => warn!("Reading reminders failed, keeping previous settings: {}", msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(Offset::parse("2d").unwrap().label(), "2d");
+        assert!(Offset::parse("12h").is_ok());
+        assert!(Offset::parse("30m").is_ok());
+        assert!(Offset::parse("2 evenings").is_ok());
+    }
+
+    #[test]
+    fn rejects_multibyte_units_without_panicking() {
+        assert!(Offset::parse("3ü").is_err());
+        assert!(Offset::parse("2日").is_err());
+        assert!(Offset::parse("日").is_err());
+        assert!(Offset::parse("d").is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Printable weekly homework sheets, one per child, for `richter report`.
+//!
+//! Markdown is for pasting into notes or email, HTML is a single file with
+//! its CSS inline so it prints or sends with nothing else needed.
+
+extern crate time;
+
+use std::collections::BTreeMap;
+
+use smh;
+use enroll;
+use calendar;
+
+/// One piece of homework on the sheet.
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub id      : i32   ,
+    pub title   : String,
+    pub class   : String,
+    pub teacher : String,
+    pub done    : bool  ,
+}
+
+/// One day on the sheet, its homework grouped by subject.
+#[derive(Debug, Clone)]
+pub struct Day {
+    pub label    : String                        , // e.g. `Monday 20 February`
+    pub subjects : BTreeMap<String, Vec<Item>>   ,
+}
+
+/// A week of homework for one child.
+#[derive(Debug, Clone)]
+pub struct Sheet {
+    pub child   : String     ,
+    pub schools : Vec<String>, // From `smh::School::name`
+    pub days    : Vec<Day>   ,
+}
+
+/// Every child's sheet for the week starting on `monday`.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub title  : String    ,
+    pub sheets : Vec<Sheet>,
+}
+
+impl Sheet {
+    /// The week from `monday` for one calendar, keeping the entries `keep` accepts.
+    /// Weekends only appear when something is due on them.
+    pub fn build<F>(cal: &calendar::Calendar, monday: &time::Tm, keep: F) -> Sheet
+        where F: Fn(&enroll::Enrollment, &smh::Entry) -> bool {
+
+        let mut schools = vec![];
+        if let Some(cache) = cal.cache() {
+            for enrollment in cal.enrollments() {
+                if let Some(sc) = cache.schools.get(&enrollment.school_id) {
+                    if !schools.contains(&sc.school.name) {
+                        schools.push(sc.school.name.clone());
+                    }
+                }
+            }
+        }
+
+        let mut days = vec![];
+        for n in 0..7 {
+            let day  = smh::days_after(monday, n);
+            let date = smh::format_day("%Y-%m-%d", &day);
+
+            let mut subjects: BTreeMap<String, Vec<Item>> = BTreeMap::new();
+            for (enrollment, entry) in cal.entries() {
+                if !entry.due.starts_with(&date) || !keep(enrollment, entry) {
+                    continue;
+                }
+                subjects.entry(entry.subject_name.clone()).or_insert(vec![]).push(Item {
+                    id      : entry.id,
+                    title   : entry.title.clone(),
+                    class   : entry.class_name.clone(),
+                    teacher : cal.teacher(enrollment, entry).map_or(String::new(), |t| t.display_name()),
+                    done    : cal.is_done(enrollment, entry),
+                });
+            }
+
+            if n < 5 || !subjects.is_empty() {
+                days.push(Day {label: smh::format_day("%A %d %B", &day), subjects: subjects});
+            }
+        }
+
+        Sheet {child: cal.profile().label().to_owned(), schools: schools, days: days}
+    }
+}
+
+// Keeps titles from SMHW from turning into markup
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn escape_md(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if "\\`*_[]<>#|".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+const CSS : &'static str = "
+body      { font-family: Helvetica, Arial, sans-serif; color: #222; margin: 2em; }
+h1        { font-size: 1.5em; margin-bottom: 0.2em; }
+h2        { font-size: 1.25em; border-bottom: 2px solid #222; padding-bottom: 0.2em; }
+h2 small  { font-weight: normal; color: #555; }
+h3        { font-size: 1em; margin: 1.2em 0 0.3em; }
+h4        { font-size: 0.9em; margin: 0.6em 0 0.2em; color: #444; }
+ul        { list-style: none; padding-left: 0; margin: 0; }
+li        { margin: 0.25em 0; }
+.box      { display: inline-block; width: 0.9em; height: 0.9em; border: 1px solid #222;
+            margin-right: 0.5em; text-align: center; line-height: 0.9em; font-size: 0.9em; }
+.done     { color: #777; }
+.meta     { color: #555; font-size: 0.85em; }
+.none     { color: #777; font-style: italic; }
+.sheet    { page-break-after: always; }
+.sheet:last-child { page-break-after: auto; }
+";
+
+impl Report {
+    pub fn new(monday: &time::Tm, sheets: Vec<Sheet>) -> Report {
+        Report {title: format!("Homework for the week of {}", smh::format_day("%d %B %Y", monday)), sheets: sheets}
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n", escape_md(&self.title));
+        for sheet in &self.sheets {
+            out.push_str(&format!("\n## {}", escape_md(&sheet.child)));
+            if !sheet.schools.is_empty() {
+                out.push_str(&format!(" — {}", escape_md(&sheet.schools.join(", "))));
+            }
+            out.push('\n');
+
+            for day in &sheet.days {
+                out.push_str(&format!("\n### {}\n\n", day.label));
+                if day.subjects.is_empty() {
+                    out.push_str("_Nothing due._\n");
+                }
+                for (subject, items) in &day.subjects {
+                    out.push_str(&format!("**{}**\n\n", escape_md(subject)));
+                    for item in items {
+                        let meta: Vec<String> = vec![&item.class, &item.teacher].into_iter()
+                                                                                .filter(|m| !m.is_empty())
+                                                                                .map(|m| escape_md(m))
+                                                                                .collect();
+                        out.push_str(&format!("- [{}] {} ({})\n", if item.done { "x" } else { " " },
+                                              escape_md(&item.title), meta.join(", ")));
+                    }
+                    out.push('\n');
+                }
+            }
+        }
+        out
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str(&format!("<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n", escape_html(&self.title), CSS));
+        out.push_str(&format!("<h1>{}</h1>\n", escape_html(&self.title)));
+
+        for sheet in &self.sheets {
+            out.push_str("<div class=\"sheet\">\n");
+            out.push_str(&format!("<h2>{}", escape_html(&sheet.child)));
+            if !sheet.schools.is_empty() {
+                out.push_str(&format!(" <small>{}</small>", escape_html(&sheet.schools.join(", "))));
+            }
+            out.push_str("</h2>\n");
+
+            for day in &sheet.days {
+                out.push_str(&format!("<h3>{}</h3>\n", escape_html(&day.label)));
+                if day.subjects.is_empty() {
+                    out.push_str("<p class=\"none\">Nothing due.</p>\n");
+                }
+                for (subject, items) in &day.subjects {
+                    out.push_str(&format!("<h4>{}</h4>\n<ul>\n", escape_html(subject)));
+                    for item in items {
+                        let meta: Vec<String> = vec![&item.class, &item.teacher].into_iter()
+                                                                                .filter(|m| !m.is_empty())
+                                                                                .map(|m| escape_html(m))
+                                                                                .collect();
+                        out.push_str(&format!("<li{}><span class=\"box\">{}</span>{} <span class=\"meta\">{}</span></li>\n",
+                                              if item.done { " class=\"done\"" } else { "" },
+                                              if item.done { "&#10003;" } else { "" },
+                                              escape_html(&item.title), meta.join(", ")));
+                    }
+                    out.push_str("</ul>\n");
+                }
+            }
+            out.push_str("</div>\n");
+        }
+
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(titles: &[&str]) -> Report {
+        let items = titles.iter().enumerate().map(|(n, title)| Item {id: n as i32, title: (*title).to_owned(), class: "9A/Ma".to_owned(),
+                                                                     teacher: "Mr J Smith".to_owned(), done: false}).collect();
+        let mut subjects = BTreeMap::new();
+        subjects.insert("Maths".to_owned(), items);
+        let day = Day {label: "Monday 20 February".to_owned(), subjects: subjects};
+        Report {title: "Homework".to_owned(), sheets: vec![Sheet {child: "alice".to_owned(), schools: vec![], days: vec![day]}]}
+    }
+
+    #[test]
+    fn html_escapes_titles() {
+        let html = report(&["<script>alert(\"hi\")</script>", "*bold*"]).to_html();
+        assert!(html.contains("&lt;script&gt;alert(&quot;hi&quot;)&lt;/script&gt;"), "{}", html);
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("*bold*"));
+    }
+
+    #[test]
+    fn markdown_escapes_titles() {
+        let markdown = report(&["<script>alert(\"hi\")</script>", "*bold*"]).to_markdown();
+        assert!(markdown.contains("- [ ] \\<script\\>alert(\"hi\")\\</script\\> (9A/Ma, Mr J Smith)\n"), "{}", markdown);
+        assert!(markdown.contains("- [ ] \\*bold\\* (9A/Ma, Mr J Smith)\n"), "{}", markdown);
+    }
+}
@@ -0,0 +1,186 @@
+//! A small inverted index over cached entries, for `richter search`.
+//!
+//! Titles, subject and class names and teacher names are split into
+//! lowercase words, each pointing at the entries it appears in along with a
+//! weight for where it appeared. The index is rebuilt after every pull.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::fs::OpenOptions;
+use std::cmp::Ordering;
+
+use smh;
+use calendar;
+use output;
+use sidecar;
+
+// How much a word counts for, by where it was found
+const TITLE_WEIGHT   : u32 = 3;
+const SUBJECT_WEIGHT : u32 = 2;
+const TEACHER_WEIGHT : u32 = 2;
+const CLASS_WEIGHT   : u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Posting {
+    subdomain : String,
+    id        : i32   ,
+    weight    : u32   ,
+}
+
+/// Words to the entries they appear in.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    terms   : BTreeMap<String, Vec<Posting>>,
+    entries : usize                          , // For weighing rare words above common ones
+}
+
+/// An entry matching a search, before recency is taken into account.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub key       : smh::EntryKey,
+    pub matched   : usize        , // How many of the search words it matched
+    pub relevance : f64          ,
+}
+
+/// Lowercase words, split on anything that isn't a letter or digit.
+pub fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+impl Index {
+    /// Indexes every entry in a cache, with teachers looked up in its schools.
+    pub fn build(cache: &calendar::Cache) -> Index {
+        let mut found: HashMap<String, HashMap<smh::EntryKey, u32>> = HashMap::new();
+        let mut entries = 0;
+
+        for &(ref enrollment, ref entry_list) in &cache.entries {
+            let employees = cache.schools.get(&enrollment.school_id).map(|sc| &sc.employees);
+            for entry in entry_list {
+                entries += 1;
+                let key     = smh::EntryKey::new(&enrollment.subdomain, entry.id);
+                let teacher = employees.and_then(|e| e.get(&entry.employee_id))
+                                       .map_or(String::new(), |t| format!("{} {} {}", t.title, t.forename, t.surname));
+
+                for &(text, weight) in [(&entry.title       , TITLE_WEIGHT  ),
+                                        (&entry.subject_name, SUBJECT_WEIGHT),
+                                        (&teacher           , TEACHER_WEIGHT),
+                                        (&entry.class_name  , CLASS_WEIGHT  ),].iter() {
+                    for word in words(text) {
+                        *found.entry(word).or_insert(HashMap::new()).entry(key.clone()).or_insert(0) += weight;
+                    }
+                }
+            }
+        }
+
+        let mut terms = BTreeMap::new();
+        for (word, keys) in found {
+            let mut postings: Vec<Posting> = keys.into_iter()
+                                                 .map(|(key, weight)| Posting {subdomain: key.subdomain, id: key.id, weight: weight})
+                                                 .collect();
+            postings.sort_by(|a, b| (&a.subdomain, a.id).cmp(&(&b.subdomain, b.id)));
+            terms.insert(word, postings);
+        }
+        Index {terms: terms, entries: entries}
+    }
+
+    pub fn load(path: &PathBuf) -> Result<Index, output::Message> {
+        OpenOptions::new().create(true).read(true).write(true).open(path)?;
+        sidecar::load(path, "Search Index")
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), output::Message> {
+        sidecar::save(self, path, "Search Index")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// Entries matching any of the words in `text`, most relevant first. A
+    /// word of three letters or more also matches longer words it starts, at half weight.
+    pub fn search(&self, text: &str) -> Vec<Hit> {
+        let mut hits: HashMap<smh::EntryKey, Hit> = HashMap::new();
+
+        for word in words(text) {
+            let mut scored: HashMap<smh::EntryKey, f64> = HashMap::new();
+            for (term, postings) in self.terms.range(word.clone()..) {
+                let factor = match (term == &word, term.starts_with(&word)) {
+                    (true , _   )                       => 1.0,
+                    (false, true) if word.len() >= 3    => 0.5,
+                    (false, true)                       => continue,
+                    (false, false)                      => break,
+                };
+
+                // Words in fewer entries say more about the ones they're in
+                let rarity = (1.0 + self.entries as f64 / postings.len() as f64).ln();
+                for posting in postings {
+                    let key   = smh::EntryKey::new(&posting.subdomain, posting.id);
+                    let score = posting.weight as f64 * rarity * factor;
+                    let best  = scored.entry(key).or_insert(0.0);
+                    if score > *best {
+                        *best = score;
+                    }
+                }
+            }
+
+            for (key, score) in scored {
+                let hit = hits.entry(key.clone()).or_insert(Hit {key: key, matched: 0, relevance: 0.0});
+                hit.matched   += 1;
+                hit.relevance += score;
+            }
+        }
+
+        let mut ranked: Vec<Hit> = hits.into_iter().map(|(_, hit)| hit).collect();
+        ranked.sort_by(|a, b| (b.matched, b.relevance).partial_cmp(&(a.matched, a.relevance)).unwrap_or(Ordering::Equal));
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use enroll;
+
+    fn entry(id: i32, title: &str, subject: &str) -> smh::Entry {
+        smh::Entry {id: id, title: title.to_owned(), class_name: String::new(), year_name: String::new(),
+                    subject_name: subject.to_owned(), employee_id: 0, issued: String::new(), due: String::new()}
+    }
+
+    fn index() -> Index {
+        let enrollment = enroll::Enrollment {subdomain: "hill".to_owned(), school_id: 1, rule: enroll::Rule::default(), except: vec![]};
+        let cache = calendar::Cache {schools : HashMap::new(),
+                                     entries : vec![(enrollment, vec![entry(1, "Fractions worksheet"      , "Maths"  ),
+                                                                      entry(2, "Reading log"              , "English"),
+                                                                      entry(3, "Fraction and decimal quiz", "Maths"  ),])],
+                                     homework: HashMap::new()};
+        Index::build(&cache)
+    }
+
+    fn ids(hits: Vec<Hit>) -> Vec<i32> {
+        hits.into_iter().map(|hit| hit.key.id).collect()
+    }
+
+    #[test]
+    fn more_words_matched_ranks_first() {
+        assert_eq!(ids(index().search("quiz maths")), vec![3, 1]);
+        assert_eq!(ids(index().search("Fractions, MATHS")), vec![1, 3]);
+    }
+
+    #[test]
+    fn rare_words_outweigh_common_ones() {
+        assert_eq!(ids(index().search("maths log"))[0], 2);
+    }
+
+    #[test]
+    fn prefixes_need_three_letters() {
+        let mut frac = ids(index().search("frac"));
+        frac.sort();
+        assert_eq!(frac, vec![1, 3]);
+        assert!(index().search("fr").is_empty());
+        assert!(index().search("history").is_empty());
+    }
+}
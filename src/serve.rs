@@ -0,0 +1,292 @@
+//! A live iCalendar feed over HTTP, for `richter serve`.
+//!
+//! Each entry becomes an all day event on its due date. The feeds are
+//!
+//! * `/calendar.ics` with every child's homework,
+//! * `/<child>/calendar.ics` with one child's, and
+//! * `/<child>/<n>/calendar.ics` with that child's `n`th enrollment, counting
+//!   from 1 in `calendar.yml` order.
+//!
+//! Feeds are rebuilt from a fresh pull every so often. Each carries an ETag and
+//! a Last-Modified that only move when its content does, so clients polling
+//! with `If-None-Match` or `If-Modified-Since` mostly get a bodiless 304, and
+//! `HEAD` gets the same headers as `GET`.
+
+extern crate hyper;
+extern crate time;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use self::hyper::server::{Handler, Server, Request, Response};
+use self::hyper::header::{Headers, ContentType, ContentLength, ETag, EntityTag, IfNoneMatch, IfModifiedSince, LastModified, HttpDate};
+use self::hyper::method::Method;
+use self::hyper::status::StatusCode;
+use self::hyper::uri::RequestUri;
+use self::hyper::mime::Mime;
+
+use smh;
+use enroll;
+use calendar;
+use caldav;
+use ical;
+use output;
+use digest;
+use profile;
+
+/// One rendered feed.
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub body     : String ,
+    pub etag     : String , // Without the quotes
+    pub modified : time::Tm, // When the body last changed, to the second
+}
+
+fn event(cal: &calendar::Calendar, enrollment: &enroll::Enrollment, entry: &smh::Entry) -> Option<ical::Component> {
+    let day  = match smh::parse_date(&entry.due) {
+        Some(day) => day,
+        None      => return None,
+    };
+    let next = smh::days_after(&day, 1);
+    let done = cal.is_done(enrollment, entry);
+
+    // Stamped with when the homework was set, so an unchanged entry renders the same every time
+    let set = smh::parse_date(&entry.issued).unwrap_or(day);
+    let teacher = cal.teacher(enrollment, entry).map_or(String::new(), |t| t.display_name());
+
+    let mut event = ical::Component::new("VEVENT");
+    event.raw ("UID"                  , &caldav::uid(&enrollment.subdomain, entry.id))
+         .raw ("DTSTAMP"              , &ical::stamp(&set))
+         .raw ("DTSTART;VALUE=DATE"   , &time::strftime("%Y%m%d", &day ).unwrap_or(String::new()))
+         .raw ("DTEND;VALUE=DATE"     , &time::strftime("%Y%m%d", &next).unwrap_or(String::new()))
+         .text("SUMMARY"              , &format!("{}{}: {}", if done { "✓ " } else { "" }, entry.subject_name, entry.title))
+         .text("DESCRIPTION"          , format!("{} {}", entry.class_name, teacher).trim())
+         .text("CATEGORIES"           , &entry.subject_name)
+         .raw ("TRANSP"               , "TRANSPARENT");
+    Some(event)
+}
+
+// Enough to tell one body from another
+fn fingerprint(body: &str) -> String {
+    format!("{:016x}", digest::fnv1a(body.as_bytes(), digest::FNV_OFFSET))
+}
+
+fn feed(name: &str, events: Vec<ical::Component>, previous: Option<&Feed>) -> Feed {
+    let body = ical::calendar(Some(name), &events);
+    let etag = fingerprint(&body);
+    match previous {
+        Some(old) if old.etag == etag => old.clone(),
+        _                             => {let mut now = time::now_utc();
+                                          now.tm_nsec = 0;
+                                          Feed {body: body, etag: etag, modified: now}},
+    }
+}
+
+/// Every feed for `cals` by its path, keeping `previous` ones that haven't changed.
+pub fn feeds(cals: &[calendar::Calendar], previous: &HashMap<String, Feed>) -> HashMap<String, Feed> {
+    let mut feeds = HashMap::new();
+    let mut all   = vec![];
+    for cal in cals {
+        let child = cal.profile().label();
+        let mut mine = vec![];
+        for (n, enrollment) in cal.enrollments().iter().enumerate() {
+            let events: Vec<ical::Component> = cal.entries().into_iter()
+                                                  .filter(|&(e, _)| e == enrollment)
+                                                  .filter_map(|(e, entry)| event(cal, e, entry))
+                                                  .collect();
+            let path = format!("/{}/{}/calendar.ics", child, n + 1);
+            let name = format!("Homework: {} {}", child, enrollment.label());
+            mine.extend(events.iter().cloned());
+            feeds.insert(path.clone(), feed(&name, events, previous.get(&path)));
+        }
+
+        let path = format!("/{}/calendar.ics", child);
+        all.extend(mine.iter().cloned());
+        feeds.insert(path.clone(), feed(&format!("Homework: {}", child), mine, previous.get(&path)));
+    }
+    feeds.insert("/calendar.ics".to_owned(), feed("Homework", all, previous.get("/calendar.ics")));
+    feeds
+}
+
+struct Feeds {
+    feeds : Arc<RwLock<HashMap<String, Feed>>>,
+}
+
+// Whether the client's copy, as described by its conditional headers, is current
+fn fresh(headers: &Headers, feed: &Feed) -> bool {
+    if let Some(tags) = headers.get::<IfNoneMatch>() {
+        return match *tags {
+            IfNoneMatch::Any           => true,
+            IfNoneMatch::Items(ref ts) => ts.iter().any(|t| t.tag() == feed.etag),
+        };
+    }
+    match headers.get::<IfModifiedSince>() {
+        Some(&IfModifiedSince(HttpDate(since))) => since.to_timespec() >= feed.modified.to_timespec(),
+        None                                    => false,
+    }
+}
+
+// Clients percent-encode paths, so a child called `Zoë` asks for `/Zo%C3%AB/calendar.ics`.
+// Anything that isn't a valid escape is kept as it is.
+fn decode_path(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match raw.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'%' && hex.chars().all(|c| c.is_digit(16)) => u8::from_str_radix(hex, 16).ok(),
+            _                                                                   => None,
+        };
+        match escaped {
+            Some(byte) => {decoded.push(byte); i += 3},
+            None       => {decoded.push(bytes[i]); i += 1},
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+impl Handler for Feeds {
+    fn handle(&self, request: Request, mut response: Response) {
+        let path = match request.uri {
+            RequestUri::AbsolutePath(ref p) => decode_path(p.split('?').next().unwrap_or("")),
+            _                               => String::new(),
+        };
+        info!("{} {}", request.method, path);
+
+        if request.method != Method::Get && request.method != Method::Head {
+            *response.status_mut() = StatusCode::MethodNotAllowed;
+            return;
+        }
+
+        let feed = match self.feeds.read() {
+            Ok(feeds) => feeds.get(&path).cloned(),
+            Err(_)    => None,
+        };
+        let feed = match feed {
+            Some(feed) => feed,
+            None       => {*response.status_mut() = StatusCode::NotFound; return},
+        };
+
+        response.headers_mut().set(ETag(EntityTag::new(false, feed.etag.clone())));
+        response.headers_mut().set(LastModified(HttpDate(feed.modified)));
+        if fresh(&request.headers, &feed) {
+            *response.status_mut() = StatusCode::NotModified;
+            return;
+        }
+
+        if let Ok(mime) = "text/calendar; charset=utf-8".parse::<Mime>() {
+            response.headers_mut().set(ContentType(mime));
+        }
+        // The headers GET would send, and no body, which dropping the response writes
+        if request.method == Method::Head {
+            response.headers_mut().set(ContentLength(feed.body.len() as u64));
+            return;
+        }
+        if let Err(err) = response.send(feed.body.as_bytes()) {
+            warn!("Couldn't send {}: {}", path, err);
+        }
+    }
+}
+
+/// Serves the profiles' feeds on `bind`, pulling every `every` minutes. Clients
+/// go on getting the feeds from before whenever a pull fails.
+pub fn serve(bind: &str, profiles: Vec<profile::Profile>, every: u64) -> Result<(), output::Message> {
+    let mut cals = vec![];
+    for profile in &profiles {
+        cals.push(calendar::Calendar::load(profile)?);
+    }
+    let shared = Arc::new(RwLock::new(feeds(&cals, &HashMap::new())));
+
+    let refreshing = shared.clone();
+    thread::spawn(move || loop {
+        thread::sleep(StdDuration::from_secs(every * 60));
+        for (cal, profile) in cals.iter_mut().zip(profiles.iter()) {
+            match calendar::Calendar::pull(profile) {
+                Ok (fresh) => *cal = fresh,
+                Err(msg)   => warn!("Pull for {} failed, keeping previous calendar: {}", profile.label(), msg),
+            }
+        }
+        let previous = refreshing.read().map(|f| f.clone()).unwrap_or(HashMap::new());
+        let rebuilt  = feeds(&cals, &previous);
+        if let Ok(mut current) = refreshing.write() {
+            *current = rebuilt;
+        }
+    });
+
+    let server = Server::http(bind).map_err(|err| output::Message::new("Serving", &format!("Binding {}", bind), &err.to_string()))?;
+    info!("Serving {} feeds on http://{}/calendar.ics", shared.read().map(|f| f.len()).unwrap_or(0), bind);
+    server.handle(Feeds {feeds: shared})
+          .map(|_| ())
+          .map_err(|err| output::Message::new("Serving", "Handling Requests", &err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(raw: &str) -> time::Tm {
+        time::strptime(raw, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn stored() -> Feed {
+        Feed {body: "BEGIN:VCALENDAR".to_owned(), etag: "0123456789abcdef".to_owned(), modified: at("2026-10-19 09:30:00")}
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(decode_path("/Zo%C3%AB/calendar.ics"), "/Zoë/calendar.ics");
+        assert_eq!(decode_path("/my%20kid/2/calendar.ics"), "/my kid/2/calendar.ics");
+        assert_eq!(decode_path("/100%/%zz/%4"), "/100%/%zz/%4");
+    }
+
+    #[test]
+    fn fresh_by_etag_before_date() {
+        let feed = stored();
+        let mut headers = Headers::new();
+        assert!(!fresh(&headers, &feed));
+
+        headers.set(IfNoneMatch::Items(vec![EntityTag::new(false, "0123456789abcdef".to_owned())]));
+        assert!(fresh(&headers, &feed));
+        headers.set(IfNoneMatch::Any);
+        assert!(fresh(&headers, &feed));
+
+        // A stale tag wins over a date that would otherwise do
+        headers.set(IfNoneMatch::Items(vec![EntityTag::new(false, "fedcba9876543210".to_owned())]));
+        headers.set(IfModifiedSince(HttpDate(at("2026-10-20 00:00:00"))));
+        assert!(!fresh(&headers, &feed));
+    }
+
+    #[test]
+    fn fresh_by_date() {
+        let feed = stored();
+        let mut headers = Headers::new();
+        headers.set(IfModifiedSince(HttpDate(at("2026-10-19 09:30:00"))));
+        assert!(fresh(&headers, &feed));
+        headers.set(IfModifiedSince(HttpDate(at("2026-10-19 09:29:59"))));
+        assert!(!fresh(&headers, &feed));
+    }
+
+    #[test]
+    fn unchanged_feeds_keep_their_validators() {
+        let first = feeds(&[], &HashMap::new());
+        let mut previous = first.clone();
+        if let Some(all) = previous.get_mut("/calendar.ics") {
+            all.modified = at("2026-10-19 09:30:00");
+        }
+
+        let rebuilt = feeds(&[], &previous);
+        assert_eq!(rebuilt["/calendar.ics"].etag, first["/calendar.ics"].etag);
+        assert_eq!(rebuilt["/calendar.ics"].modified, at("2026-10-19 09:30:00"));
+
+        // Whereas a body that did change gets a new tag and date
+        let mut changed = previous.clone();
+        if let Some(all) = changed.get_mut("/calendar.ics") {
+            all.etag = "0123456789abcdef".to_owned();
+        }
+        let rebuilt = feeds(&[], &changed);
+        assert_eq!(rebuilt["/calendar.ics"].etag, first["/calendar.ics"].etag);
+        assert!(rebuilt["/calendar.ics"].modified != at("2026-10-19 09:30:00"));
+    }
+}
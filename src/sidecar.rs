@@ -0,0 +1,40 @@
+//! Sidecar files hold local state (completion, notes) that lives beside the
+//! cache but must never be thrown away with it.
+
+extern crate serde;
+extern crate serde_json;
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::fs::File;
+
+use self::serde::{Serialize, Deserialize};
+
+use output;
+
+/// Reads state from `path`, an empty file giving the default. `what` names it in errors.
+pub fn load<T: Deserialize + Default>(path: &PathBuf, what: &str) -> Result<T, output::Message> {
+    let mut raw = String::new();
+    File::open(path)?.read_to_string(&mut raw)?;
+
+    if raw.trim() == "" {
+        return Ok(T::default());
+    }
+
+    match serde_json::from_str(&raw) {
+        Ok (state) => Ok(state),
+        Err(msg)   => Err(output::Message::new(&format!("Loading {}", what),
+                                               &format!("Reading {}", path.display()),
+                                               &msg.to_string())),
+    }
+}
+
+/// Writes `state` to `path` as JSON. `what` names it in errors.
+pub fn save<T: Serialize>(state: &T, path: &PathBuf, what: &str) -> Result<(), output::Message> {
+    match serde_json::to_string(state) {
+        Ok (out) => Ok(File::create(path)?.write_all(out.as_bytes())?),
+        Err(msg) => Err(output::Message::new(&format!("Saving {}", what),
+                                             &format!("Serialising {}", what),
+                                             &msg.to_string())),
+    }
+}
@@ -1,3 +1,7 @@
+//! The SMHW data model, as returned by `interface::Interface`.
+
+extern crate time;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct School   {
     pub id          : i32,
@@ -43,7 +47,7 @@ pub struct Class    {
     pub year_name   : String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Entry {
     pub id          : i32,
     pub title       : String,
@@ -54,3 +58,155 @@ pub struct Entry {
     pub issued      : String, // These two to date in future?
     pub due         : String, // Look at: time, chrono crates
 }
+
+impl Entry {
+    pub fn due_date(&self) -> Option<time::Tm> {
+        parse_date(&self.due)
+    }
+
+    pub fn issued_date(&self) -> Option<time::Tm> {
+        parse_date(&self.issued)
+    }
+
+    /// Whole days from today until the entry is due, negative once overdue.
+    pub fn days_until_due(&self) -> Option<i64> {
+        self.due_date().map(|due| (due - today()).num_days())
+    }
+}
+
+/// Parses the date part of an SMHW timestamp like `2017-02-20T00:00:00.000Z`.
+pub fn parse_date(raw: &str) -> Option<time::Tm> {
+    let date: String = raw.chars().take(10).collect();
+    match time::strptime(&date, "%Y-%m-%d") {
+        Ok (tm) => Some(time::at_utc(tm.to_timespec())),
+        Err(_)  => None                                ,
+    }
+}
+
+/// Midnight at the start of today's local date, expressed like `parse_date` output.
+pub fn today() -> time::Tm {
+    let mut now = time::now();
+    now.tm_hour   = 0;
+    now.tm_min    = 0;
+    now.tm_sec    = 0;
+    now.tm_nsec   = 0;
+    now.tm_utcoff = 0;
+    now.tm_isdst  = 0;
+    time::at_utc(now.to_timespec())
+}
+
+/// Days from today for `today`, `tomorrow`, `yesterday`, `+3d`, `-1w` or `2017-03-01`.
+pub fn parse_day_offset(raw: &str) -> Option<i64> {
+    match raw.to_lowercase().as_str() {
+        "today"     => return Some( 0),
+        "tomorrow"  => return Some( 1),
+        "yesterday" => return Some(-1),
+        _           => {},
+    }
+    if let Some(date) = parse_date(raw) {
+        return Some((date - today()).num_days());
+    }
+
+    if !(raw.starts_with('+') || raw.starts_with('-')) {
+        return None;
+    }
+    let body  = &raw[1..];
+    let unit  = match body.chars().last() {
+        Some(unit) => unit,
+        None       => return None,
+    };
+    let count = match body[..body.len() - unit.len_utf8()].parse::<i64>() {
+        Ok(n)  => if raw.starts_with('-') { -n } else { n },
+        Err(_) => return None,
+    };
+    match unit {
+        'd' => Some(count    ),
+        'w' => Some(count * 7),
+        _   => None           ,
+    }
+}
+
+/// The day `days` after (or before, if negative) a day from `today` or `parse_date`.
+pub fn days_after(day: &time::Tm, days: i64) -> time::Tm {
+    time::at_utc(day.to_timespec() + time::Duration::days(days))
+}
+
+/// The Monday on or before a day from `today` or `parse_date`.
+pub fn week_start(day: &time::Tm) -> time::Tm {
+    days_after(day, -(((day.tm_wday + 6) % 7) as i64))
+}
+
+/// A day through `strftime`, empty if the format is bad.
+pub fn format_day(format: &str, day: &time::Tm) -> String {
+    time::strftime(format, day).unwrap_or(String::new())
+}
+
+/// Identifies an entry across schools. Entry ids are only unique within a
+/// school, so local state is keyed by subdomain too.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EntryKey {
+    pub subdomain   : String,
+    pub id          : i32,
+}
+
+impl EntryKey {
+    pub fn new(subdomain: &str, id: i32) -> EntryKey {
+        EntryKey {subdomain: subdomain.to_owned(), id: id}
+    }
+}
+
+impl Employee {
+    /// How the school addresses them, e.g. `Mr J Smith`.
+    pub fn display_name(&self) -> String {
+        let initial: String = self.forename.chars().take(1).collect();
+        vec![self.title.as_str(), initial.as_str(), self.surname.as_str()]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id          : i32,
+    pub file_name   : String,
+    pub file_url    : String,
+    pub file_size   : i64,
+}
+
+/// The full record behind a calendar `Entry`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Homework {
+    pub id                : i32,
+    pub title             : String,
+    pub description       : String, // HTML, see `plain_description`
+    pub submission_method : String,
+    pub duration          : Option<i32>, // Estimated minutes
+    pub web_links         : Vec<String>,
+    pub attachments       : Vec<Attachment>,
+}
+
+impl Homework {
+    /// The description with its HTML tags stripped and common entities decoded.
+    pub fn plain_description(&self) -> String {
+        let mut plain  = String::new();
+        let mut in_tag = false;
+        for c in self.description.chars() {
+            match c {
+                '<'          => in_tag = true ,
+                '>'          => in_tag = false,
+                _ if in_tag  => {}            ,
+                _            => plain.push(c) ,
+            }
+        }
+        plain.replace("&nbsp;", " ")
+             .replace("&lt;"  , "<")
+             .replace("&gt;"  , ">")
+             .replace("&quot;", "\"")
+             .replace("&#39;" , "'")
+             .replace("&amp;" , "&")
+             .trim()
+             .to_owned()
+    }
+}
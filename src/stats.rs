@@ -0,0 +1,136 @@
+//! Homework load in numbers, for `richter stats`.
+
+extern crate serde_json;
+extern crate time;
+
+use std::collections::HashMap;
+
+use smh;
+use query;
+use output;
+
+/// How many entries share one subject, teacher, week or day.
+#[derive(Debug, Clone, Serialize)]
+pub struct Count {
+    pub name  : String,
+    pub count : usize ,
+}
+
+/// Totals over a set of entries.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub entries           : usize      ,
+    pub first_due         : String     ,
+    pub last_due          : String     ,
+    pub average_lead_days : Option<f64>, // Issued to due, over entries with both dates
+    pub by_subject        : Vec<Count> , // Most first
+    pub by_teacher        : Vec<Count> , // Most first
+    pub by_week           : Vec<Count> , // Named by their Monday, in date order
+    pub by_weekday        : Vec<Count> , // Monday to Sunday
+    pub busiest_days      : Vec<Count> , // The few dates with most due, most first
+}
+
+const BUSIEST_DAYS : usize = 5;
+const WEEKDAYS     : [&'static str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+
+// Largest count first, then by name so the order is stable
+fn ranked(counts: HashMap<String, usize>) -> Vec<Count> {
+    let mut ranked: Vec<Count> = counts.into_iter().map(|(name, count)| Count {name: name, count: count}).collect();
+    ranked.sort_by(|a, b| (b.count, &a.name).cmp(&(a.count, &b.name)));
+    ranked
+}
+
+impl Stats {
+    /// Counts up entries as a query sees them, teachers included.
+    pub fn gather(facts: &[query::Facts]) -> Stats {
+        let mut subjects : HashMap<String, usize> = HashMap::new();
+        let mut teachers : HashMap<String, usize> = HashMap::new();
+        let mut weeks    : HashMap<String, usize> = HashMap::new();
+        let mut dates    : HashMap<String, usize> = HashMap::new();
+        let mut weekdays = [0; 7];
+        let mut leads    = vec![];
+
+        for f in facts {
+            let entry = f.entry;
+            *subjects.entry(entry.subject_name.clone()).or_insert(0) += 1;
+            *teachers.entry(if f.teacher.is_empty() { "Unknown".to_owned() } else { f.teacher.clone() }).or_insert(0) += 1;
+
+            if let Some(due) = entry.due_date() {
+                let monday  = smh::week_start(&due);
+                let weekday = (due - monday).num_days() as usize;
+                *weeks.entry(smh::format_day("%Y-%m-%d", &monday)).or_insert(0) += 1;
+                *dates.entry(smh::format_day("%Y-%m-%d", &due   )).or_insert(0) += 1;
+                weekdays[weekday] += 1;
+
+                if let Some(issued) = entry.issued_date() {
+                    leads.push((due - issued).num_days());
+                }
+            }
+        }
+
+        let mut by_week: Vec<Count> = weeks.into_iter().map(|(name, count)| Count {name: name, count: count}).collect();
+        by_week.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut busiest_days = ranked(dates);
+        busiest_days.truncate(BUSIEST_DAYS);
+
+        let average = match leads.len() {
+            0 => None,
+            n => Some(leads.iter().sum::<i64>() as f64 / n as f64),
+        };
+
+        let due_dates: Vec<String> = facts.iter().map(|f| f.entry.due.chars().take(10).collect()).collect();
+
+        Stats {entries           : facts.len(),
+               first_due         : due_dates.iter().min().cloned().unwrap_or(String::new()),
+               last_due          : due_dates.iter().max().cloned().unwrap_or(String::new()),
+               average_lead_days : average,
+               by_subject        : ranked(subjects),
+               by_teacher        : ranked(teachers),
+               by_week           : by_week,
+               by_weekday        : WEEKDAYS.iter().zip(weekdays.iter()).map(|(name, &count)| Count {name: (*name).to_owned(), count: count}).collect(),
+               busiest_days      : busiest_days,}
+    }
+
+    pub fn to_json(&self) -> Result<String, output::Message> {
+        serde_json::to_string_pretty(self).map_err(|msg| output::Message::new("Reporting Stats",
+                                                                                "Serialising Stats",
+                                                                                &msg.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: i32, subject: &str, issued: &str, due: &str) -> smh::Entry {
+        smh::Entry {id: id, title: format!("Task {}", id), class_name: "9A".to_owned(), year_name: "Year 9".to_owned(),
+                    subject_name: subject.to_owned(), employee_id: 0, issued: issued.to_owned(), due: due.to_owned()}
+    }
+
+    fn counts(counts: &[Count]) -> Vec<(&str, usize)> {
+        counts.iter().map(|c| (c.name.as_str(), c.count)).collect()
+    }
+
+    #[test]
+    fn counts_over_two_weeks() {
+        let entries = vec![entry(1, "Maths"  , "2026-10-01T00:00:00.000Z", "2026-10-06T00:00:00.000Z"),
+                           entry(2, "Maths"  , "2026-10-04T00:00:00.000Z", "2026-10-06T00:00:00.000Z"),
+                           entry(3, "English", "2026-10-12T00:00:00.000Z", "2026-10-13T00:00:00.000Z"),
+                           entry(4, "English", "2026-10-09T00:00:00.000Z", "2026-10-14T00:00:00.000Z"),
+                           entry(5, "Science", ""                        , "2026-10-16T00:00:00.000Z"),];
+        let facts: Vec<query::Facts> = entries.iter().map(|e| query::Facts {entry: e, teacher: String::new(), done: false, tags: vec![],
+                                                                             notes: vec![], child: String::new()}).collect();
+        let stats = Stats::gather(&facts);
+
+        assert_eq!(stats.entries, 5);
+        assert_eq!((stats.first_due.as_str(), stats.last_due.as_str()), ("2026-10-06", "2026-10-16"));
+        assert_eq!(counts(&stats.by_week), vec![("2026-10-05", 2), ("2026-10-12", 3)]);
+        assert_eq!(counts(&stats.by_weekday), vec![("Monday", 0), ("Tuesday", 3), ("Wednesday", 1), ("Thursday", 0),
+                                                   ("Friday", 1), ("Saturday", 0), ("Sunday", 0)]);
+        assert_eq!(counts(&stats.busiest_days), vec![("2026-10-06", 2), ("2026-10-13", 1), ("2026-10-14", 1), ("2026-10-16", 1)]);
+        assert_eq!(counts(&stats.by_teacher), vec![("Unknown", 5)]);
+        // Leads of 5, 2, 1 and 5 days, the entry without an issue date left out
+        assert_eq!(stats.average_lead_days, Some(3.25));
+    }
+}